@@ -0,0 +1,202 @@
+//! Throughput of the per-object operations of a transaction.
+//!
+//! Run with `cargo bench --bench crud`. Each case is timed over a fixed number
+//! of operations against an in-memory database and reported per operation.
+//!
+//! The `raw` cases run the statements the transaction issues for inserts and
+//! lookups with rusqlite directly, once building and preparing the SQL for
+//! every operation as before statements were cached, and once through the
+//! statement cache, to show what caching saves.
+
+use orm::{Connection, Object, ObjectId};
+
+use std::time::{Duration, Instant};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const OPERATIONS: usize = 20_000;
+const ROUNDS: usize = 5;
+
+#[derive(Object)]
+struct Record {
+    name: String,
+    email: String,
+    score: i64,
+    ratio: f64,
+    active: bool,
+}
+
+fn record(i: usize) -> Record {
+    Record {
+        name: format!("name-{}", i),
+        email: format!("user{}@example.com", i),
+        score: i as i64,
+        ratio: i as f64 / 3.0,
+        active: i.is_multiple_of(2),
+    }
+}
+
+fn populate(conn: &mut Connection) -> Vec<ObjectId> {
+    let tx = conn.new_transaction().unwrap();
+    let ids = (0..OPERATIONS)
+        .map(|i| tx.create(record(i)).unwrap().id())
+        .collect();
+    tx.commit().unwrap();
+    ids
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn bench_create() -> Duration {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let start = Instant::now();
+    for i in 0..OPERATIONS {
+        tx.create(record(i)).unwrap();
+    }
+    tx.commit().unwrap();
+    start.elapsed()
+}
+
+fn bench_create_many() -> Duration {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let start = Instant::now();
+    tx.create_many((0..OPERATIONS).map(record)).unwrap();
+    tx.commit().unwrap();
+    start.elapsed()
+}
+
+fn bench_get() -> Duration {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let ids = populate(&mut conn);
+    let tx = conn.new_transaction().unwrap();
+    let start = Instant::now();
+    for id in ids {
+        tx.get::<Record>(id).unwrap();
+    }
+    start.elapsed()
+}
+
+fn bench_update() -> Duration {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let ids = populate(&mut conn);
+    let tx = conn.new_transaction().unwrap();
+    let txs = ids
+        .into_iter()
+        .map(|id| tx.get::<Record>(id).unwrap())
+        .collect::<Vec<_>>();
+    for tx in &txs {
+        tx.borrow_mut().score += 1;
+    }
+    drop(txs);
+    let start = Instant::now();
+    tx.commit().unwrap();
+    start.elapsed()
+}
+
+fn bench_delete() -> Duration {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let ids = populate(&mut conn);
+    let tx = conn.new_transaction().unwrap();
+    for id in ids {
+        tx.get::<Record>(id).unwrap().delete();
+    }
+    let start = Instant::now();
+    tx.commit().unwrap();
+    start.elapsed()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const RAW_TABLE: &str = "CREATE TABLE Record(id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                         name TEXT,email TEXT,score BIGINT,ratio REAL,active BOOLEAN)";
+
+/// Prepares `sql` through the statement cache, or from scratch unless `cached`.
+fn prepare<'c>(
+    conn: &'c rusqlite::Connection,
+    sql: &str,
+    cached: bool,
+) -> rusqlite::CachedStatement<'c> {
+    if !cached {
+        conn.flush_prepared_statement_cache();
+    }
+    conn.prepare_cached(sql).unwrap()
+}
+
+fn bench_raw_insert(cached: bool) -> Duration {
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    conn.execute(RAW_TABLE, []).unwrap();
+    let start = Instant::now();
+    conn.execute("BEGIN", []).unwrap();
+    for i in 0..OPERATIONS {
+        let r = record(i);
+        let sql = format!(
+            "INSERT INTO {}({}) VALUES(?1,?2,?3,?4,?5)",
+            "Record", "name,email,score,ratio,active"
+        );
+        prepare(&conn, &sql, cached)
+            .execute(rusqlite::params![
+                r.name, r.email, r.score, r.ratio, r.active
+            ])
+            .unwrap();
+    }
+    conn.execute("COMMIT", []).unwrap();
+    start.elapsed()
+}
+
+fn bench_raw_get(cached: bool) -> Duration {
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    conn.execute(RAW_TABLE, []).unwrap();
+    for i in 0..OPERATIONS {
+        let r = record(i);
+        conn.execute(
+            "INSERT INTO Record(name,email,score,ratio,active) VALUES(?1,?2,?3,?4,?5)",
+            rusqlite::params![r.name, r.email, r.score, r.ratio, r.active],
+        )
+        .unwrap();
+    }
+    let start = Instant::now();
+    for id in 1..=OPERATIONS as i64 {
+        let sql = format!(
+            "SELECT {} FROM {} WHERE id = ?1",
+            "name,email,score,ratio,active", "Record"
+        );
+        prepare(&conn, &sql, cached)
+            .query_row([id], |row| {
+                Ok(Record {
+                    name: row.get(0)?,
+                    email: row.get(1)?,
+                    score: row.get(2)?,
+                    ratio: row.get(3)?,
+                    active: row.get(4)?,
+                })
+            })
+            .unwrap();
+    }
+    start.elapsed()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn run(name: &str, bench: impl Fn() -> Duration) {
+    let best = (0..ROUNDS).map(|_| bench()).min().unwrap();
+    println!(
+        "{:<22} {:>10.2?} total {:>10.2?}/op",
+        name,
+        best,
+        best / OPERATIONS as u32
+    );
+}
+
+fn main() {
+    run("create", bench_create);
+    run("create_many", bench_create_many);
+    run("get", bench_get);
+    run("update", bench_update);
+    run("delete", bench_delete);
+    run("raw insert, uncached", || bench_raw_insert(false));
+    run("raw insert, cached", || bench_raw_insert(true));
+    run("raw get, uncached", || bench_raw_get(false));
+    run("raw get, cached", || bench_raw_get(true));
+}
@@ -1,8 +1,12 @@
 use proc_macro::TokenStream;
-// use quote::{quote, ToTokens};
-// use syn::{parse_macro_input, Attribute, Data, DeriveInput, FieldsNamed, Ident, LitStr, Type};
+use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
-use syn::{parse_macro_input, Attribute, DeriveInput, MetaList};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    Attribute, DeriveInput, Expr, Generics, Ident, Lit, MetaList, Token, Type,
+};
 
 fn extract_attribute(attrs: &[Attribute], name: &str, default: String) -> String {
     attrs
@@ -25,12 +29,146 @@ fn extract_attribute(attrs: &[Attribute], name: &str, default: String) -> String
         .unwrap_or(default)
 }
 
-#[proc_macro_derive(Object, attributes(table_name, column_name))]
-pub fn derive_object(input: TokenStream) -> TokenStream {
-    let input: DeriveInput = parse_macro_input!(input);
-    let input_ident = input.ident;
-    let table_name = extract_attribute(&input.attrs, "table_name", input_ident.to_string());
-    let struct_ = match input.data {
+fn has_flag(attrs: &[Attribute], name: &str) -> bool {
+    attrs.iter().any(|attr| match attr.parse_meta() {
+        Ok(syn::Meta::Path(path)) => path.is_ident(name),
+        Ok(_) if attr.path.is_ident(name) => panic!("#[{}] takes no arguments", name),
+        _ => false,
+    })
+}
+
+/// A single item of an `#[orm(...)]` attribute: `name`, `name = value` or
+/// `name(a, b)`.
+enum OrmArg {
+    Flag(Ident),
+    Value(Ident, Box<Expr>),
+    List(Ident, Vec<Ident>),
+}
+
+impl OrmArg {
+    fn name(&self) -> &Ident {
+        match self {
+            OrmArg::Flag(name) | OrmArg::Value(name, _) | OrmArg::List(name, _) => name,
+        }
+    }
+}
+
+impl Parse for OrmArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            Ok(OrmArg::Value(name, input.parse()?))
+        } else if input.peek(syn::token::Paren) {
+            let content;
+            syn::parenthesized!(content in input);
+            let idents = Punctuated::<Ident, Token![,]>::parse_terminated(&content)?;
+            Ok(OrmArg::List(name, idents.into_iter().collect()))
+        } else {
+            Ok(OrmArg::Flag(name))
+        }
+    }
+}
+
+fn orm_args(attrs: &[Attribute]) -> Vec<OrmArg> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("orm"))
+        .flat_map(|attr| {
+            attr.parse_args_with(Punctuated::<OrmArg, Token![,]>::parse_terminated)
+                .unwrap_or_else(|e| panic!("invalid #[orm] attribute: {}", e))
+        })
+        .collect()
+}
+
+fn string_value(name: &str, expr: &Expr) -> String {
+    match expr {
+        Expr::Lit(syn::ExprLit {
+            lit: Lit::Str(s), ..
+        }) => s.value(),
+        _ => panic!("expected string literal for #[orm({})]", name),
+    }
+}
+
+/// Renders a literal as an SQL literal for a `DEFAULT` clause.
+fn default_sql(expr: &Expr) -> String {
+    match expr {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Str(s) => format!("'{}'", s.value().replace('\'', "''")),
+            Lit::Int(i) => i.base10_digits().to_string(),
+            Lit::Float(f) => f.base10_digits().to_string(),
+            Lit::Bool(b) => (b.value as u8).to_string(),
+            _ => panic!("unsupported literal for #[orm(default)]"),
+        },
+        Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) if matches!(
+            &**expr,
+            Expr::Lit(syn::ExprLit {
+                lit: Lit::Int(_) | Lit::Float(_),
+                ..
+            })
+        ) =>
+        {
+            format!("-{}", default_sql(expr))
+        }
+        _ => panic!("expected a literal for #[orm(default)]"),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct FieldInfo<'a> {
+    ident: &'a Ident,
+    ty: &'a Type,
+    column_name: String,
+    flatten: bool,
+    /// Runtime-only field, not stored and filled from `Default` on load.
+    skip: bool,
+    /// SQL literal for the `DEFAULT` clause of the column.
+    default: Option<String>,
+    unique: bool,
+    index: bool,
+    check: Option<String>,
+    primary_key: bool,
+    fulltext: bool,
+    created_at: bool,
+    updated_at: bool,
+    /// The type whose key the field holds, from `#[orm(references = Type)]`.
+    references: Option<Expr>,
+    /// The item type of a `Vec` field stored in a collection table.
+    element: Option<&'a Type>,
+}
+
+/// Returns `T` for a `Vec<T>` field type. `Vec<u8>` is a byte string and not a
+/// collection.
+fn element_type(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != "Vec" {
+        return None;
+    }
+    let element = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => {
+            match args.args.first()? {
+                syn::GenericArgument::Type(element) => element,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    match element {
+        Type::Path(path) if path.path.is_ident("u8") => None,
+        element => Some(element),
+    }
+}
+
+fn parse_fields(data: syn::Data) -> Vec<(Ident, Type, Vec<Attribute>)> {
+    let struct_ = match data {
         syn::Data::Struct(struct_) => struct_,
         _ => panic!("only structs are supported"),
     };
@@ -39,50 +177,650 @@ pub fn derive_object(input: TokenStream) -> TokenStream {
         syn::Fields::Unit => syn::punctuated::Punctuated::default(),
         _ => panic!("unnamed fields are unsupported"),
     };
-    let fields_count = named_fields.len();
-    let field_idents: Vec<_> = named_fields
+    named_fields
+        .into_iter()
+        .map(|field| (field.ident.unwrap(), field.ty, field.attrs))
+        .collect()
+}
+
+fn field_infos(fields: &[(Ident, Type, Vec<Attribute>)]) -> Vec<FieldInfo<'_>> {
+    fields
         .iter()
-        .map(|field| field.ident.as_ref().unwrap())
-        .collect();
-    let to_row = field_idents
+        .map(|(ident, ty, attrs)| {
+            let mut info = FieldInfo {
+                ident,
+                ty,
+                column_name: extract_attribute(attrs, "column_name", ident.to_string()),
+                flatten: has_flag(attrs, "flatten"),
+                skip: false,
+                default: None,
+                unique: false,
+                index: false,
+                check: None,
+                primary_key: false,
+                fulltext: false,
+                created_at: false,
+                updated_at: false,
+                references: None,
+                element: element_type(ty),
+            };
+            for arg in orm_args(attrs) {
+                match (arg.name().to_string().as_str(), &arg) {
+                    ("skip", OrmArg::Flag(_)) => info.skip = true,
+                    ("default", OrmArg::Value(_, value)) => info.default = Some(default_sql(value)),
+                    ("unique", OrmArg::Flag(_)) => info.unique = true,
+                    ("index", OrmArg::Flag(_)) => info.index = true,
+                    ("primary_key", OrmArg::Flag(_)) => info.primary_key = true,
+                    ("fulltext", OrmArg::Flag(_)) => info.fulltext = true,
+                    ("created_at", OrmArg::Flag(_)) => info.created_at = true,
+                    ("updated_at", OrmArg::Flag(_)) => info.updated_at = true,
+                    ("references", OrmArg::Value(_, value)) => {
+                        info.references = Some((**value).clone())
+                    }
+                    ("check", OrmArg::Value(_, value)) => {
+                        info.check = Some(string_value("check", value))
+                    }
+                    (name, _) => panic!("unsupported field attribute #[orm({})]", name),
+                }
+            }
+            if info.flatten && info.element.is_some() {
+                panic!("cannot flatten collection field '{}'", ident)
+            }
+            let constrained = info.default.is_some()
+                || info.unique
+                || info.index
+                || info.check.is_some()
+                || info.primary_key
+                || info.fulltext
+                || info.created_at
+                || info.updated_at
+                || info.references.is_some();
+            if constrained && (info.skip || info.flatten || info.element.is_some()) {
+                panic!("column attributes are only supported on plain fields")
+            }
+            info
+        })
+        .collect()
+}
+
+impl<'a> FieldInfo<'a> {
+    /// Whether the field is stored in the columns of the owner's table.
+    fn is_column(&self) -> bool {
+        !self.skip && self.element.is_none()
+    }
+
+    fn is_collection(&self) -> bool {
+        !self.skip && self.element.is_some()
+    }
+
+    /// Whether the field declares table-level constraints, which only object
+    /// types support.
+    fn has_constraints(&self) -> bool {
+        self.unique
+            || self.index
+            || self.check.is_some()
+            || self.primary_key
+            || self.fulltext
+            || self.created_at
+            || self.updated_at
+            || self.references.is_some()
+    }
+}
+
+/// Statements pushing `orm::object::Field`s of the given fields into `fields`.
+fn push_fields(infos: &[FieldInfo]) -> Vec<TokenStream2> {
+    infos
         .iter()
-        .map(|ident| quote! { (&self.#ident).into() });
-    let column_names = named_fields.iter().map(|field| {
-        extract_attribute(
-            &field.attrs,
-            "column_name",
-            field.ident.as_ref().unwrap().to_string(),
-        )
-    });
-    let types = named_fields.iter().map(|field| &field.ty);
-    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+        .filter(|info| info.is_column())
+        .map(|info| {
+            let FieldInfo {
+                ident,
+                ty,
+                column_name,
+                ..
+            } = info;
+            let default = match &info.default {
+                Some(default) => quote! { Some(#default) },
+                None => quote! { None },
+            };
+            let primary_key = info.primary_key.then(|| {
+                quote! { primary_key.push(fields.len()); }
+            });
+            // Only text can be indexed for full-text search.
+            let fulltext = info.fulltext.then(|| {
+                quote! {
+                    let _: fn(&#ty) -> &String = |text| text;
+                    fulltext.push(fields.len());
+                }
+            });
+            if info.flatten {
+                quote! {
+                    orm::object::push_embedded_fields::<#ty>(
+                        &mut fields,
+                        #column_name,
+                        stringify!(#ident),
+                    );
+                }
+            } else {
+                quote! {
+                    #primary_key
+                    #fulltext
+                    fields.push(orm::object::Field {
+                        column_name: #column_name,
+                        data_type: <#ty as orm::data::ToDataType>::DATA_TYPE,
+                        attr_name: stringify!(#ident),
+                        default: #default,
+                    });
+                }
+            }
+        })
+        .collect()
+}
+
+/// Expressions decoding each field from `reader`, or from `collections` for
+/// collection fields.
+fn read_fields(infos: &[FieldInfo]) -> Vec<TokenStream2> {
+    infos
+        .iter()
+        .map(|info| {
+            let FieldInfo { ident, ty, .. } = info;
+            if info.skip {
+                quote! { #ident: Default::default() }
+            } else if let Some(element) = info.element {
+                quote! { #ident: collections.read::<#element>()? }
+            } else if info.flatten {
+                quote! { #ident: <#ty as orm::object::Embeddable>::read(reader)? }
+            } else {
+                quote! { #ident: reader.read()? }
+            }
+        })
+        .collect()
+}
+
+/// Statements appending the values of each field to `row`.
+fn write_fields(infos: &[FieldInfo]) -> Vec<TokenStream2> {
+    infos
+        .iter()
+        .filter(|info| info.is_column())
+        .map(|info| {
+            let FieldInfo { ident, ty, .. } = info;
+            if info.flatten {
+                quote! { <#ty as orm::object::Embeddable>::write(&self.#ident, row); }
+            } else {
+                quote! { row.push((&self.#ident).into()); }
+            }
+        })
+        .collect()
+}
+
+/// Statements pushing an `orm::object::Collection` for each collection field
+/// into `collections`.
+fn push_collections(infos: &[FieldInfo], table_name: &str, type_name: &str) -> Vec<TokenStream2> {
+    infos
+        .iter()
+        .filter(|info| info.is_collection())
+        .filter_map(|info| {
+            let FieldInfo {
+                ident, column_name, ..
+            } = info;
+            let element = info.element?;
+            Some(quote! {
+                collections.push(orm::object::Collection::new::<#element>(
+                    #table_name,
+                    #column_name,
+                    stringify!(#ident),
+                    #type_name,
+                ));
+            })
+        })
+        .collect()
+}
+
+/// Expressions building the `orm::object::Index`es and `orm::object::Check`s
+/// declared on the fields and on the struct itself.
+fn constraints(
+    table_name: &str,
+    struct_args: &[OrmArg],
+    infos: &[FieldInfo],
+) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let column_of = |ident: &Ident| {
+        infos
+            .iter()
+            .find(|info| info.ident == ident)
+            .filter(|info| info.is_column() && !info.flatten)
+            .map(|info| info.column_name.clone())
+            .unwrap_or_else(|| panic!("'{}' is not a plain field", ident))
+    };
+    let index = |columns: Vec<String>, unique: bool| {
+        let suffix = if unique { "key" } else { "idx" };
+        let name = format!("{}_{}_{}", table_name, columns.join("_"), suffix);
+        quote! {
+            orm::object::Index {
+                name: #name,
+                columns: vec![#(#columns),*],
+                unique: #unique,
+            }
+        }
+    };
+    let check = |name: String, expr: &str| {
+        quote! {
+            orm::object::Check {
+                name: #name,
+                expr: #expr,
+            }
+        }
+    };
+
+    let mut indexes = Vec::new();
+    let mut checks = Vec::new();
+    for info in infos {
+        if info.unique {
+            indexes.push(index(vec![info.column_name.clone()], true));
+        }
+        if info.index {
+            indexes.push(index(vec![info.column_name.clone()], false));
+        }
+        if let Some(expr) = &info.check {
+            checks.push(check(
+                format!("{}_{}_check", table_name, info.column_name),
+                expr,
+            ));
+        }
+    }
+    let mut struct_checks = 0;
+    for arg in struct_args {
+        match (arg.name().to_string().as_str(), arg) {
+            ("index", OrmArg::List(_, idents)) => {
+                indexes.push(index(idents.iter().map(column_of).collect(), false))
+            }
+            ("unique", OrmArg::List(_, idents)) => {
+                indexes.push(index(idents.iter().map(column_of).collect(), true))
+            }
+            // Not constraints; read by `derive_object`.
+            ("hooks" | "soft_delete" | "audit", OrmArg::Flag(_)) => {}
+            ("check", OrmArg::Value(_, value)) => {
+                struct_checks += 1;
+                let name = format!("{}_check_{}", table_name, struct_checks);
+                checks.push(check(name, &string_value("check", value)));
+            }
+            (name, _) => panic!("unsupported struct attribute #[orm({})]", name),
+        }
+    }
+    (indexes, checks)
+}
+
+/// Expressions building the `orm::object::ForeignKey`s declared on the fields.
+/// Each also checks that the field has the key type of the referenced type.
+fn foreign_keys(infos: &[FieldInfo]) -> Vec<TokenStream2> {
+    infos
+        .iter()
+        .filter_map(|info| {
+            let references = info.references.as_ref()?;
+            let (ty, column_name) = (info.ty, &info.column_name);
+            Some(quote! {
+                orm::object::ForeignKey {
+                    column_name: #column_name,
+                    references: {
+                        let _: fn(&#ty) -> &<#references as orm::object::Object>::Key = |key| key;
+                        <#references as orm::object::Object>::schema
+                    },
+                }
+            })
+        })
+        .collect()
+}
+
+/// An `Object::set_timestamps` implementation assigning the `created_at` and
+/// `updated_at` fields, if there are any.
+fn timestamps(infos: &[FieldInfo]) -> Option<TokenStream2> {
+    if !infos.iter().any(|info| info.created_at || info.updated_at) {
+        return None;
+    }
+    let created = infos
+        .iter()
+        .filter(|info| info.created_at)
+        .map(|info| info.ident);
+    let updated = infos
+        .iter()
+        .filter(|info| info.updated_at)
+        .map(|info| info.ident);
+    Some(quote! {
+        fn set_timestamps(&mut self, now: orm::Timestamp, inserted: bool) {
+            if inserted {
+                #(self.#created = now;)*
+            }
+            #(self.#updated = now;)*
+        }
+    })
+}
+
+/// A `<Name>Columns` struct with a typed `orm::query::Column` per plain field,
+/// and an inherent `columns()` constructor on the object type.
+fn columns(input: &DeriveInput, infos: &[FieldInfo]) -> TokenStream2 {
+    let DeriveInput {
+        ident,
+        vis,
+        generics,
+        ..
+    } = input;
+    let columns_ident = Ident::new(&format!("{}Columns", ident), ident.span());
+    let infos = infos
+        .iter()
+        .filter(|info| info.is_column() && !info.flatten)
+        .collect::<Vec<_>>();
+    let idents = infos.iter().map(|info| info.ident).collect::<Vec<_>>();
+    let types = infos.iter().map(|info| info.ty);
+    let column_names = infos.iter().map(|info| &info.column_name);
+    let doc = format!("Typed columns of [`{}`].", ident);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        #[doc = #doc]
+        #vis struct #columns_ident #impl_generics #where_clause {
+            #(pub #idents: orm::query::Column<#ident #ty_generics, #types>,)*
+        }
+
+        impl #impl_generics #ident #ty_generics #where_clause {
+            pub fn columns() -> #columns_ident #ty_generics {
+                #columns_ident {
+                    #(#idents: orm::query::Column::new(#column_names),)*
+                }
+            }
+        }
+    }
+}
+
+/// An expression returning a `&'static #ty` initialized once by `init`.
+fn static_value(generics: &Generics, ty: TokenStream2, init: TokenStream2) -> TokenStream2 {
+    if generics.params.is_empty() {
+        quote! {{
+            static VALUE: std::sync::OnceLock<#ty> = std::sync::OnceLock::new();
+            VALUE.get_or_init(|| #init)
+        }}
+    } else {
+        quote! { orm::object::generic_static::<Self, #ty>(|| #init) }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[proc_macro_derive(Object, attributes(table_name, column_name, flatten, orm))]
+pub fn derive_object(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = parse_macro_input!(input);
+    let input_ident = &input.ident;
+    let table_name = extract_attribute(&input.attrs, "table_name", input_ident.to_string());
+    let fields = parse_fields(input.data.clone());
+    let infos = field_infos(&fields);
+    // The FTS5 index refers to rows by rowid, which only the implicit `id`
+    // column keeps stable, e.g. across VACUUM.
+    if infos.iter().any(|info| info.fulltext) && infos.iter().any(|info| info.primary_key) {
+        panic!("fulltext fields are not supported in types with primary key fields")
+    }
+    let columns = columns(&input, &infos);
+    let push_fields = push_fields(&infos);
+    let read_fields = read_fields(&infos);
+    let write_fields = write_fields(&infos);
     let type_name = input_ident.to_string();
+    let push_collections = push_collections(&infos, &table_name, &type_name);
+    let struct_args = orm_args(&input.attrs);
+    let has_flag = |flag: &str| {
+        struct_args
+            .iter()
+            .any(|arg| matches!(arg, OrmArg::Flag(name) if name == flag))
+    };
+    let soft_delete = has_flag("soft_delete");
+    let audit = has_flag("audit");
+    let (indexes, checks) = constraints(&table_name, &struct_args, &infos);
+    let foreign_keys = foreign_keys(&infos);
+    let collection_rows = infos
+        .iter()
+        .filter(|info| info.is_collection())
+        .map(|info| {
+            let ident = info.ident;
+            quote! { orm::object::element_rows(&self.#ident) }
+        });
+    let key_fields: Vec<_> = infos.iter().filter(|info| info.primary_key).collect();
+    let (key_type, key_value) = match key_fields.as_slice() {
+        [] => (quote! { orm::ObjectId }, quote! { rowid }),
+        [info] => {
+            let (ident, ty) = (info.ident, info.ty);
+            (quote! { #ty }, quote! { self.#ident.clone() })
+        }
+        _ => {
+            let idents = key_fields.iter().map(|info| info.ident);
+            let tys = key_fields.iter().map(|info| info.ty);
+            (
+                quote! { (#(#tys,)*) },
+                quote! { (#(self.#idents.clone(),)*) },
+            )
+        }
+    };
+    let schema = static_value(
+        &input.generics,
+        quote! { orm::object::Schema },
+        quote! {{
+            let mut fields = Vec::new();
+            let mut primary_key = Vec::new();
+            let mut fulltext = Vec::new();
+            #(#push_fields)*
+            let mut collections = Vec::new();
+            #(#push_collections)*
+            orm::object::Schema {
+                table_name: #table_name,
+                fields,
+                primary_key,
+                fulltext,
+                collections,
+                indexes: vec![#(#indexes),*],
+                checks: vec![#(#checks),*],
+                foreign_keys: vec![#(#foreign_keys),*],
+                soft_delete: #soft_delete,
+                audit: #audit,
+                type_name: #type_name,
+                statements: Default::default(),
+            }
+        }},
+    );
+    let set_timestamps = timestamps(&infos);
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let hooks = (!has_flag("hooks")).then(|| {
+        quote! {
+            impl #impl_generics orm::object::Hooks for #input_ident #ty_generics
+            #where_clause
+            {
+            }
+        }
+    });
+    let soft_delete_impl = soft_delete.then(|| {
+        quote! {
+            impl #impl_generics orm::object::SoftDelete for #input_ident #ty_generics
+            #where_clause
+            {
+            }
+        }
+    });
+    let audit_impl = audit.then(|| {
+        quote! {
+            impl #impl_generics orm::object::Audited for #input_ident #ty_generics
+            #where_clause
+            {
+            }
+        }
+    });
+    let searchable_impl = infos.iter().any(|info| info.fulltext).then(|| {
+        quote! {
+            impl #impl_generics orm::object::Searchable for #input_ident #ty_generics
+            #where_clause
+            {
+            }
+        }
+    });
     let output = quote! {
         impl #impl_generics orm::object::Object for #input_ident #ty_generics
         #where_clause
         {
-            fn from_row(row: orm::storage::Row) -> Self {
-                let row: [orm::data::Value; #fields_count] = row.try_into().ok().unwrap();
-                match row {
-                    [#(#field_idents,)*] => Self {
-                        #(#field_idents: #field_idents.into(),)*
-                    },
+            type Key = #key_type;
+
+            fn from_row(
+                row: orm::storage::Row,
+                collections: Vec<Vec<orm::storage::Row>>,
+            ) -> orm::Result<Self> {
+                let reader = &mut orm::storage::RowReader::new(Self::schema(), row);
+                let collections =
+                    &mut orm::storage::CollectionReader::new(Self::schema(), collections);
+                Ok(Self {
+                    #(#read_fields,)*
+                })
+            }
+
+            fn to_row(&self) -> orm::storage::Row<'_> {
+                let mut row = Vec::new();
+                {
+                    let row = &mut row;
+                    #(#write_fields)*
                 }
+                row
             }
 
-            fn to_row(&self) -> orm::storage::Row {
-                vec![#(#to_row,)*]
+            fn collections_to_rows(&self) -> Vec<Vec<orm::storage::Row<'_>>> {
+                vec![#(#collection_rows,)*]
             }
 
-            const SCHEMA: orm::object::Schema = orm::object::Schema {
-                table_name: #table_name,
-                fields: &[#(orm::object::Field {
-                    column_name: #column_names,
-                    data_type: <#types as orm::data::ToDataType>::DATA_TYPE,
-                    attr_name: stringify!(#field_idents),
-                },)*],
+            #[allow(unused_variables)]
+            fn key(&self, rowid: orm::ObjectId) -> Self::Key {
+                #key_value
+            }
+
+            fn schema() -> &'static orm::object::Schema {
+                #schema
+            }
+
+            #set_timestamps
+        }
+
+        #hooks
+
+        #soft_delete_impl
+
+        #audit_impl
+
+        #searchable_impl
+
+        #columns
+    };
+    output.into()
+}
+
+#[proc_macro_derive(Embeddable, attributes(column_name, flatten, orm))]
+pub fn derive_embeddable(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = parse_macro_input!(input);
+    let input_ident = input.ident;
+    let fields = parse_fields(input.data);
+    let infos = field_infos(&fields);
+    if let Some(info) = infos.iter().find(|info| info.is_collection()) {
+        panic!(
+            "collection field '{}' is not supported in embeddable types",
+            info.ident
+        )
+    }
+    if let Some(info) = infos.iter().find(|info| info.has_constraints()) {
+        panic!(
+            "constraints on field '{}' are not supported in embeddable types",
+            info.ident
+        )
+    }
+    let push_fields = push_fields(&infos);
+    let read_fields = read_fields(&infos);
+    let write_fields = write_fields(&infos);
+    let embedded_fields = static_value(
+        &input.generics,
+        quote! { Vec<orm::object::Field> },
+        quote! {{
+            let mut fields = Vec::new();
+            #(#push_fields)*
+            fields
+        }},
+    );
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let output = quote! {
+        impl #impl_generics orm::object::Embeddable for #input_ident #ty_generics
+        #where_clause
+        {
+            fn fields() -> &'static [orm::object::Field] {
+                #embedded_fields
+            }
+
+            fn read(reader: &mut orm::storage::RowReader) -> orm::Result<Self> {
+                Ok(Self {
+                    #(#read_fields,)*
+                })
+            }
+
+            fn write<'a>(&'a self, row: &mut orm::storage::Row<'a>) {
+                #(#write_fields)*
+            }
+        }
+    };
+    output.into()
+}
+
+#[proc_macro_derive(FromRow, attributes(column_name, flatten, orm))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = parse_macro_input!(input);
+    let input_ident = input.ident;
+    let fields = parse_fields(input.data);
+    let infos = field_infos(&fields);
+    if let Some(info) = infos.iter().find(|info| info.is_collection()) {
+        panic!(
+            "collection field '{}' is not supported in row types",
+            info.ident
+        )
+    }
+    if let Some(info) = infos.iter().find(|info| info.has_constraints()) {
+        panic!(
+            "constraints on field '{}' are not supported in row types",
+            info.ident
+        )
+    }
+    let push_fields = push_fields(&infos);
+    let read_fields = read_fields(&infos);
+    let type_name = input_ident.to_string();
+    let schema = static_value(
+        &input.generics,
+        quote! { orm::object::Schema },
+        quote! {{
+            let mut fields = Vec::new();
+            #(#push_fields)*
+            orm::object::Schema {
+                table_name: #type_name,
+                fields,
+                primary_key: Vec::new(),
+                fulltext: Vec::new(),
+                collections: Vec::new(),
+                indexes: Vec::new(),
+                checks: Vec::new(),
+                foreign_keys: Vec::new(),
+                soft_delete: false,
+                audit: false,
                 type_name: #type_name,
-            };
+                statements: Default::default(),
+            }
+        }},
+    );
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let output = quote! {
+        impl #impl_generics orm::object::FromRow for #input_ident #ty_generics
+        #where_clause
+        {
+            fn schema() -> &'static orm::object::Schema {
+                #schema
+            }
+
+            fn from_row(row: orm::storage::Row) -> orm::Result<Self> {
+                let reader = &mut orm::storage::RowReader::new(Self::schema(), row);
+                Ok(Self {
+                    #(#read_fields,)*
+                })
+            }
         }
     };
     output.into()
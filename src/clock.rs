@@ -0,0 +1,27 @@
+use crate::data::Timestamp;
+
+use std::time::SystemTime;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The source of the current time for `#[orm(created_at)]` and
+/// `#[orm(updated_at)]` fields, set with `Connection::set_clock`. Closures
+/// returning a `Timestamp` are clocks, so tests can use a fixed time.
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+}
+
+impl<F: Fn() -> Timestamp> Clock for F {
+    fn now(&self) -> Timestamp {
+        self()
+    }
+}
+
+/// The system time, used unless another clock is set.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        SystemTime::now().into()
+    }
+}
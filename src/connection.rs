@@ -1,9 +1,12 @@
-use crate::{storage::StorageTransaction, Result, Transaction};
+use crate::{storage::StorageTransaction, Clock, Result, SystemClock, Transaction};
 
-use std::path::Path;
+use std::{path::Path, rc::Rc};
 
 ////////////////////////////////////////////////////////////////////////////////
 
+// Each table uses a handful of statements; keep those of a few dozen tables.
+const STATEMENT_CACHE_CAPACITY: usize = 128;
+
 trait StorageConnection {
     fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>>;
 }
@@ -18,22 +21,37 @@ impl StorageConnection for rusqlite::Connection {
 
 pub struct Connection {
     inner: Box<dyn StorageConnection>,
+    clock: Rc<dyn Clock>,
 }
 
 impl Connection {
     pub fn open_sqlite_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Ok(Self {
-            inner: Box::new(rusqlite::Connection::open(path)?),
-        })
+        Self::new(rusqlite::Connection::open(path)?)
     }
 
     pub fn open_in_memory() -> Result<Self> {
-        Ok(Self {
-            inner: Box::new(rusqlite::Connection::open_in_memory()?),
-        })
+        Self::new(rusqlite::Connection::open_in_memory()?)
     }
 
     pub fn new_transaction(&mut self) -> Result<Transaction<'_>> {
-        Ok(Transaction::new(self.inner.new_transaction()?))
+        Ok(Transaction::new(
+            self.inner.new_transaction()?,
+            self.clock.clone(),
+        ))
+    }
+
+    /// Replaces the system clock used by the transactions of this connection.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Rc::new(clock);
+    }
+
+    fn new(conn: rusqlite::Connection) -> Result<Self> {
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        // SQLite leaves foreign keys unchecked unless asked per connection.
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Ok(Self {
+            inner: Box::new(conn),
+            clock: Rc::new(SystemClock),
+        })
     }
 }
@@ -1,21 +1,58 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    fmt,
+    hash::{Hash, Hasher},
+    time::{Duration, SystemTime},
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
 pub struct ObjectId(i64);
 
+/// A point in time, stored as microseconds since the Unix epoch.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+pub struct Timestamp(i64);
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DataType {
     String,
     Bytes,
+    Int8,
+    Int16,
+    Int32,
     Int64,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Float32,
     Float64,
     Bool,
 }
 
+impl DataType {
+    pub fn sql_type(&self) -> &'static str {
+        match self {
+            DataType::String => "TEXT",
+            DataType::Bytes => "BLOB",
+            DataType::Int8 => "TINYINT",
+            DataType::Int16 => "SMALLINT",
+            DataType::Int32 => "INTEGER",
+            DataType::Int64 => "BIGINT",
+            DataType::UInt8 => "UNSIGNED TINYINT",
+            DataType::UInt16 => "UNSIGNED SMALLINT",
+            DataType::UInt32 => "UNSIGNED INTEGER",
+            DataType::UInt64 => "UNSIGNED BIGINT",
+            DataType::Float32 => "FLOAT",
+            DataType::Float64 => "REAL",
+            DataType::Bool => "TINYINT",
+        }
+    }
+}
+
 pub trait ToDataType {
     const DATA_TYPE: DataType;
 }
@@ -32,6 +69,14 @@ impl ToDataType for i64 {
     const DATA_TYPE: DataType = DataType::Int64;
 }
 
+impl ToDataType for u64 {
+    const DATA_TYPE: DataType = DataType::UInt64;
+}
+
+impl ToDataType for f32 {
+    const DATA_TYPE: DataType = DataType::Float32;
+}
+
 impl ToDataType for f64 {
     const DATA_TYPE: DataType = DataType::Float64;
 }
@@ -40,16 +85,111 @@ impl ToDataType for bool {
     const DATA_TYPE: DataType = DataType::Bool;
 }
 
+impl ToDataType for ObjectId {
+    const DATA_TYPE: DataType = DataType::Int64;
+}
+
+impl ToDataType for Timestamp {
+    const DATA_TYPE: DataType = DataType::Int64;
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Failure to decode a `Value` into a Rust type. It carries no schema context;
+/// `RowReader` attaches the field it was reading.
+#[derive(Debug, thiserror::Error)]
+pub enum ValueError {
+    #[error("unexpected type {0}")]
+    UnexpectedType(&'static str),
+    #[error("value {0} is out of range")]
+    OutOfRange(String),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Values compare structurally: floats are equal when their bits are, so that
+/// values can key the identity map.
+#[derive(Clone, Debug)]
 pub enum Value<'a> {
     String(Cow<'a, str>),
     Bytes(Cow<'a, [u8]>),
     Int64(i64),
+    UInt64(u64),
     Float64(f64),
     Bool(bool),
 }
 
+impl<'a> Value<'a> {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "String",
+            Value::Bytes(_) => "Bytes",
+            Value::Int64(_) => "Int64",
+            Value::UInt64(_) => "UInt64",
+            Value::Float64(_) => "Float64",
+            Value::Bool(_) => "Bool",
+        }
+    }
+
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Value::String(x) => Value::String(Cow::Owned(x.into_owned())),
+            Value::Bytes(x) => Value::Bytes(Cow::Owned(x.into_owned())),
+            Value::Int64(x) => Value::Int64(x),
+            Value::UInt64(x) => Value::UInt64(x),
+            Value::Float64(x) => Value::Float64(x),
+            Value::Bool(x) => Value::Bool(x),
+        }
+    }
+}
+
+impl<'a, 'b> PartialEq<Value<'b>> for Value<'a> {
+    fn eq(&self, other: &Value<'b>) -> bool {
+        match (self, other) {
+            (Value::String(x), Value::String(y)) => x == y,
+            (Value::Bytes(x), Value::Bytes(y)) => x == y,
+            (Value::Int64(x), Value::Int64(y)) => x == y,
+            (Value::UInt64(x), Value::UInt64(y)) => x == y,
+            (Value::Float64(x), Value::Float64(y)) => x.to_bits() == y.to_bits(),
+            (Value::Bool(x), Value::Bool(y)) => x == y,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> Eq for Value<'a> {}
+
+impl<'a> Hash for Value<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::String(x) => x.hash(state),
+            Value::Bytes(x) => x.hash(state),
+            Value::Int64(x) => x.hash(state),
+            Value::UInt64(x) => x.hash(state),
+            Value::Float64(x) => x.to_bits().hash(state),
+            Value::Bool(x) => x.hash(state),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Value<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(x) => write!(f, "{:?}", x),
+            Value::Bytes(x) => {
+                write!(f, "x'")?;
+                x.iter().try_for_each(|b| write!(f, "{:02x}", b))?;
+                write!(f, "'")
+            }
+            Value::Int64(x) => write!(f, "{}", x),
+            Value::UInt64(x) => write!(f, "{}", x),
+            Value::Float64(x) => write!(f, "{}", x),
+            Value::Bool(x) => write!(f, "{}", x),
+        }
+    }
+}
+
 impl ObjectId {
     pub fn into_i64(&self) -> i64 {
         self.0
@@ -62,12 +202,60 @@ impl From<i64> for ObjectId {
     }
 }
 
+impl<'a> From<&'a ObjectId> for Value<'a> {
+    fn from(value: &'a ObjectId) -> Self {
+        Value::Int64(value.0)
+    }
+}
+
+impl Timestamp {
+    pub fn from_micros(micros: i64) -> Self {
+        Self(micros)
+    }
+
+    pub fn as_micros(&self) -> i64 {
+        self.0
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(since) => Self(since.as_micros() as i64),
+            Err(e) => Self(-(e.duration().as_micros() as i64)),
+        }
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(timestamp: Timestamp) -> Self {
+        let offset = Duration::from_micros(timestamp.0.unsigned_abs());
+        if timestamp.0 < 0 {
+            SystemTime::UNIX_EPOCH - offset
+        } else {
+            SystemTime::UNIX_EPOCH + offset
+        }
+    }
+}
+
+impl<'a> From<&'a Timestamp> for Value<'a> {
+    fn from(value: &'a Timestamp) -> Self {
+        Value::Int64(value.0)
+    }
+}
+
 impl<'a> From<&'a String> for Value<'a> {
     fn from(value: &'a String) -> Self {
         Value::String(value.into())
     }
 }
 
+impl<'a> From<&'a str> for Value<'a> {
+    fn from(value: &'a str) -> Self {
+        Value::String(value.into())
+    }
+}
+
 impl<'a> From<&'a Vec<u8>> for Value<'a> {
     fn from(value: &'a Vec<u8>) -> Self {
         Value::Bytes(value.into())
@@ -80,6 +268,18 @@ impl<'a> From<&'a i64> for Value<'a> {
     }
 }
 
+impl<'a> From<&'a u64> for Value<'a> {
+    fn from(value: &'a u64) -> Self {
+        Value::UInt64(*value)
+    }
+}
+
+impl<'a> From<&'a f32> for Value<'a> {
+    fn from(value: &'a f32) -> Self {
+        Value::Float64((*value).into())
+    }
+}
+
 impl<'a> From<&'a f64> for Value<'a> {
     fn from(value: &'a f64) -> Self {
         Value::Float64(*value)
@@ -92,47 +292,232 @@ impl<'a> From<&'a bool> for Value<'a> {
     }
 }
 
-impl<'a> From<Value<'a>> for String {
-    fn from(value: Value<'a>) -> Self {
+impl<'a> TryFrom<Value<'a>> for String {
+    type Error = ValueError;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
         match value {
-            Value::String(x) => x.into_owned(),
-            _ => panic!("Expected orm::data::Value::String"),
+            Value::String(x) => Ok(x.into_owned()),
+            v => Err(ValueError::UnexpectedType(v.type_name())),
         }
     }
 }
 
-impl<'a> From<Value<'a>> for Vec<u8> {
-    fn from(value: Value<'a>) -> Self {
+impl<'a> TryFrom<Value<'a>> for Vec<u8> {
+    type Error = ValueError;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
         match value {
-            Value::Bytes(x) => x.into_owned(),
-            _ => panic!("Expected orm::data::Value::Bytes"),
+            Value::Bytes(x) => Ok(x.into_owned()),
+            v => Err(ValueError::UnexpectedType(v.type_name())),
         }
     }
 }
 
-impl<'a> From<Value<'a>> for i64 {
-    fn from(value: Value<'a>) -> Self {
+impl<'a> TryFrom<Value<'a>> for i64 {
+    type Error = ValueError;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
         match value {
-            Value::Int64(x) => x,
-            _ => panic!("Expected orm::data::Value::Int64"),
+            Value::Int64(x) => Ok(x),
+            v => Err(ValueError::UnexpectedType(v.type_name())),
         }
     }
 }
 
-impl<'a> From<Value<'a>> for f64 {
-    fn from(value: Value<'a>) -> Self {
+impl<'a> TryFrom<Value<'a>> for ObjectId {
+    type Error = ValueError;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
+        i64::try_from(value).map(ObjectId)
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for Timestamp {
+    type Error = ValueError;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
+        i64::try_from(value).map(Timestamp)
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for u64 {
+    type Error = ValueError;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
         match value {
-            Value::Float64(x) => x,
-            _ => panic!("Expected orm::data::Value::Float64"),
+            Value::UInt64(x) => Ok(x),
+            v => Err(ValueError::UnexpectedType(v.type_name())),
         }
     }
 }
 
-impl<'a> From<Value<'a>> for bool {
-    fn from(value: Value<'a>) -> Self {
+impl<'a> TryFrom<Value<'a>> for f32 {
+    type Error = ValueError;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
         match value {
-            Value::Bool(x) => x,
-            _ => panic!("Expected orm::data::Value::Bool"),
+            Value::Float64(x) => narrow_float(x),
+            v => Err(ValueError::UnexpectedType(v.type_name())),
+        }
+    }
+}
+
+/// Narrows `x` to `f32`, failing unless it is in range. Infinities and NaN
+/// stay as they are.
+pub(crate) fn narrow_float(x: f64) -> Result<f32, ValueError> {
+    if x.is_finite() && x.abs() > f32::MAX as f64 {
+        return Err(ValueError::OutOfRange(x.to_string()));
+    }
+    Ok(x as f32)
+}
+
+impl<'a> TryFrom<Value<'a>> for f64 {
+    type Error = ValueError;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
+        match value {
+            Value::Float64(x) => Ok(x),
+            v => Err(ValueError::UnexpectedType(v.type_name())),
+        }
+    }
+}
+
+impl<'a> TryFrom<Value<'a>> for bool {
+    type Error = ValueError;
+
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(x) => Ok(x),
+            v => Err(ValueError::UnexpectedType(v.type_name())),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Narrow integers are widened to `Value::Int64`; decoding checks the range.
+macro_rules! impl_narrow_integer {
+    ($($ty:ty => $data_type:ident),* $(,)?) => {
+        $(
+            impl ToDataType for $ty {
+                const DATA_TYPE: DataType = DataType::$data_type;
+            }
+
+            impl<'a> From<&'a $ty> for Value<'a> {
+                fn from(value: &'a $ty) -> Self {
+                    Value::Int64((*value).into())
+                }
+            }
+
+            impl<'a> TryFrom<Value<'a>> for $ty {
+                type Error = ValueError;
+
+                fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
+                    match value {
+                        Value::Int64(x) => {
+                            x.try_into().map_err(|_| ValueError::OutOfRange(x.to_string()))
+                        }
+                        v => Err(ValueError::UnexpectedType(v.type_name())),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_narrow_integer! {
+    i8 => Int8,
+    i16 => Int16,
+    i32 => Int32,
+    u8 => UInt8,
+    u16 => UInt16,
+    u32 => UInt32,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Encodes `values` as a hex string, each value as a tag byte followed by its
+/// payload. Decoded by `decode_values`.
+pub(crate) fn encode_values(values: &[Value]) -> String {
+    let mut bytes = Vec::new();
+    for value in values {
+        match value {
+            Value::String(x) => {
+                bytes.push(b's');
+                bytes.extend((x.len() as u64).to_be_bytes());
+                bytes.extend(x.as_bytes());
+            }
+            Value::Bytes(x) => {
+                bytes.push(b'b');
+                bytes.extend((x.len() as u64).to_be_bytes());
+                bytes.extend(x.iter());
+            }
+            Value::Int64(x) => {
+                bytes.push(b'i');
+                bytes.extend(x.to_be_bytes());
+            }
+            Value::UInt64(x) => {
+                bytes.push(b'u');
+                bytes.extend(x.to_be_bytes());
+            }
+            Value::Float64(x) => {
+                bytes.push(b'f');
+                bytes.extend(x.to_bits().to_be_bytes());
+            }
+            Value::Bool(x) => {
+                bytes.push(b'o');
+                bytes.push(*x as u8);
+            }
+        }
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes values encoded by `encode_values`, or `None` if `encoded` is not
+/// such an encoding.
+pub(crate) fn decode_values(encoded: &str) -> Option<Vec<Value<'static>>> {
+    if !encoded.len().is_multiple_of(2) || !encoded.is_ascii() {
+        return None;
+    }
+    let bytes = (0..encoded.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&encoded[i..i + 2], 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    let mut rest = bytes.as_slice();
+    let mut take = |n: usize| -> Option<&[u8]> {
+        if rest.len() < n {
+            return None;
         }
+        let (head, tail) = rest.split_at(n);
+        rest = tail;
+        Some(head)
+    };
+    let mut values = Vec::new();
+    while let Some(tag) = take(1) {
+        let value = match tag[0] {
+            b's' | b'b' => {
+                let len = u64::from_be_bytes(take(8)?.try_into().ok()?);
+                let payload = take(usize::try_from(len).ok()?)?.to_vec();
+                if tag[0] == b's' {
+                    Value::String(Cow::Owned(String::from_utf8(payload).ok()?))
+                } else {
+                    Value::Bytes(Cow::Owned(payload))
+                }
+            }
+            b'i' => Value::Int64(i64::from_be_bytes(take(8)?.try_into().ok()?)),
+            b'u' => Value::UInt64(u64::from_be_bytes(take(8)?.try_into().ok()?)),
+            b'f' => Value::Float64(f64::from_bits(u64::from_be_bytes(
+                take(8)?.try_into().ok()?,
+            ))),
+            b'o' => match take(1)?[0] {
+                0 => Value::Bool(false),
+                1 => Value::Bool(true),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        values.push(value);
     }
+    Some(values)
 }
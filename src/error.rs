@@ -1,4 +1,8 @@
-use crate::{data::DataType, object::Schema, ObjectId};
+use crate::{
+    data::{DataType, Value, ValueError},
+    object::Schema,
+    storage::RowSlice,
+};
 
 use thiserror::Error;
 
@@ -11,9 +15,32 @@ pub enum Error {
     #[error(transparent)]
     UnexpectedType(Box<UnexpectedTypeError>),
     #[error(transparent)]
+    OutOfRange(Box<OutOfRangeError>),
+    #[error(transparent)]
     MissingColumn(Box<MissingColumnError>),
+    #[error(transparent)]
+    ConstraintViolation(Box<ConstraintViolationError>),
+    #[error(transparent)]
+    KeyChanged(Box<KeyChangedError>),
+    #[error(transparent)]
+    InvalidJoin(Box<InvalidJoinError>),
     #[error("database is locked")]
     LockConflict,
+    /// A cached object with pending changes, or one that has to be reloaded,
+    /// is still borrowed through its `Tx` handle.
+    #[error("object is borrowed")]
+    ObjectBorrowed,
+    #[error("invalid pagination cursor")]
+    InvalidCursor,
+    #[error("page size must be positive")]
+    InvalidPageSize,
+    /// A history entry whose values cannot be decoded, with its sequence
+    /// number.
+    #[error("corrupt history entry {0}")]
+    CorruptHistory(u64),
+    /// An error returned by application code, e.g. a `Hooks` method.
+    #[error(transparent)]
+    User(Box<dyn std::error::Error>),
     #[error("storage error: {0}")]
     Storage(#[source] Box<dyn std::error::Error>),
 }
@@ -56,30 +83,179 @@ pub fn map_rusqlite_error(err: rusqlite::Error, schema: &Schema) -> Error {
                 got_type: got_type.to_string(),
             }))
         }
+        rusqlite::Error::IntegralValueOutOfRange(column_index, value) => {
+            out_of_range_error(schema, column_index, value.to_string())
+        }
+        rusqlite::Error::FromSqlConversionFailure(column_index, _, err)
+            if err.is::<ValueError>() =>
+        {
+            map_value_error(*err.downcast().unwrap(), schema, column_index)
+        }
         rusqlite::Error::SqliteFailure(_, Some(msg)) if { find_column_name(&msg).is_some() } => {
-            let column_name = find_column_name(&msg).unwrap();
-            let field = schema
+            // Columns in filters and orderings are qualified with the table.
+            let column_name = find_column_name(&msg).unwrap().rsplit('.').next().unwrap();
+            // Columns that are not fields, e.g. `deleted_at` or the `id` and
+            // `parent_*` key columns, are reported under their own name.
+            let attr_name = schema
                 .fields
                 .iter()
                 .find(|f| f.column_name == column_name)
-                .unwrap();
+                .map_or(column_name, |f| f.attr_name);
             Error::MissingColumn(Box::new(MissingColumnError {
+                type_name: schema.type_name,
+                attr_name: attr_name.to_string(),
+                table_name: schema.table_name,
+                column_name: column_name.to_string(),
+            }))
+        }
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error {
+                code: rusqlite::ErrorCode::ConstraintViolation,
+                extended_code: _,
+            },
+            Some(msg),
+        ) => Error::ConstraintViolation(Box::new(ConstraintViolationError {
+            table_name: schema.table_name,
+            constraint: find_constraint_name(&msg, schema),
+        })),
+        e => e.into(),
+    }
+}
+
+/// Like `map_rusqlite_error`, for statements over the tables of both `schema`
+/// and `joined`.
+pub fn map_join_error(err: rusqlite::Error, schema: &Schema, joined: &Schema) -> Error {
+    let in_joined = match &err {
+        rusqlite::Error::SqliteFailure(_, Some(msg)) => find_column_name(msg).is_some_and(|c| {
+            c.strip_prefix(joined.table_name)
+                .is_some_and(|c| c.starts_with('.'))
+        }),
+        _ => false,
+    };
+    map_rusqlite_error(err, if in_joined { joined } else { schema })
+}
+
+/// Names the constraint reported by SQLite, e.g. `CHECK constraint failed: name`
+/// or `UNIQUE constraint failed: table.a, table.b`. Unique violations are
+/// mapped back to the index declared over the same columns.
+fn find_constraint_name(msg: &str, schema: &Schema) -> String {
+    let detail = match msg.split_once(" constraint failed: ") {
+        Some((_, detail)) => detail,
+        None => return msg.to_string(),
+    };
+    if !msg.starts_with("UNIQUE") {
+        return detail.to_string();
+    }
+    let columns = detail
+        .split(", ")
+        .map(|c| c.rsplit('.').next().unwrap())
+        .collect::<Vec<_>>();
+    schema
+        .indexes
+        .iter()
+        .find(|i| i.unique && i.columns == columns)
+        .map(|i| i.name.to_string())
+        .unwrap_or_else(|| detail.to_string())
+}
+
+pub fn out_of_range_error(schema: &Schema, column_index: usize, value: String) -> Error {
+    let field = &schema.fields[column_index];
+    Error::OutOfRange(Box::new(OutOfRangeError {
+        type_name: schema.type_name,
+        attr_name: field.attr_name,
+        table_name: schema.table_name,
+        column_name: field.column_name,
+        expected_type: field.data_type,
+        value,
+    }))
+}
+
+pub fn missing_column_error(schema: &Schema, column_index: usize) -> Error {
+    let field = &schema.fields[column_index];
+    Error::MissingColumn(Box::new(MissingColumnError {
+        type_name: schema.type_name,
+        attr_name: field.attr_name.to_string(),
+        table_name: schema.table_name,
+        column_name: field.column_name.to_string(),
+    }))
+}
+
+/// The implicit `id` key column is missing from a result.
+pub fn missing_key_column_error(schema: &Schema) -> Error {
+    Error::MissingColumn(Box::new(MissingColumnError {
+        type_name: schema.type_name,
+        attr_name: "id".to_string(),
+        table_name: schema.table_name,
+        column_name: "id".to_string(),
+    }))
+}
+
+pub fn map_value_error(err: ValueError, schema: &Schema, column_index: usize) -> Error {
+    match err {
+        ValueError::UnexpectedType(got_type) => {
+            let field = &schema.fields[column_index];
+            Error::UnexpectedType(Box::new(UnexpectedTypeError {
                 type_name: schema.type_name,
                 attr_name: field.attr_name,
                 table_name: schema.table_name,
                 column_name: field.column_name,
+                expected_type: field.data_type,
+                got_type: got_type.to_string(),
             }))
         }
-        e => e.into(),
+        ValueError::OutOfRange(value) => out_of_range_error(schema, column_index, value),
     }
 }
 
-pub fn map_rusqlite_error_with_id(err: rusqlite::Error, schema: &Schema, id: ObjectId) -> Error {
+/// A `NULL` read from the field of `schema` at `column_index`, which none of
+/// the field types can hold.
+pub fn null_value_error(schema: &Schema, column_index: usize) -> Error {
+    map_value_error(ValueError::UnexpectedType("Null"), schema, column_index)
+}
+
+fn format_key(schema: &Schema, key: &RowSlice) -> String {
+    schema
+        .key_columns()
+        .into_iter()
+        .zip(key)
+        .map(|((c, _), v)| format!("{} = {}", c, v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn not_found_error(schema: &Schema, key: &RowSlice) -> Error {
+    Error::NotFound(Box::new(NotFoundError {
+        key: format_key(schema, key),
+        type_name: schema.type_name,
+    }))
+}
+
+/// The key fields of the object loaded with `key` were modified.
+pub fn key_changed_error(schema: &Schema, key: &RowSlice) -> Error {
+    Error::KeyChanged(Box::new(KeyChangedError {
+        key: format_key(schema, key),
+        type_name: schema.type_name,
+    }))
+}
+
+pub fn invalid_join_error(schema: &Schema, column_name: &'static str, joined: &Schema) -> Error {
+    Error::InvalidJoin(Box::new(InvalidJoinError {
+        type_name: schema.type_name,
+        column_name,
+        joined_type_name: joined.type_name,
+    }))
+}
+
+pub fn not_found_by_error(schema: &Schema, column_name: &str, value: &Value) -> Error {
+    Error::NotFound(Box::new(NotFoundError {
+        key: format!("{} = {}", column_name, value),
+        type_name: schema.type_name,
+    }))
+}
+
+pub fn map_rusqlite_error_with_key(err: rusqlite::Error, schema: &Schema, key: &RowSlice) -> Error {
     match err {
-        rusqlite::Error::QueryReturnedNoRows => Error::NotFound(Box::new(NotFoundError {
-            object_id: id,
-            type_name: schema.type_name,
-        })),
+        rusqlite::Error::QueryReturnedNoRows => not_found_error(schema, key),
         e => map_rusqlite_error(e, schema),
     }
 }
@@ -87,10 +263,31 @@ pub fn map_rusqlite_error_with_id(err: rusqlite::Error, schema: &Schema, id: Obj
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Error, Debug)]
-#[error("object is not found: type '{type_name}', id {object_id:?}")]
+#[error("object is not found: type '{type_name}', {key}")]
 pub struct NotFoundError {
-    pub object_id: ObjectId,
+    pub key: String,
+    pub type_name: &'static str,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("primary key fields cannot be modified: type '{type_name}', {key}")]
+pub struct KeyChangedError {
+    pub key: String,
+    pub type_name: &'static str,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A join on a column not declared with `#[orm(references = ...)]` to the
+/// joined type, or of a type with itself.
+#[derive(Error, Debug)]
+#[error("cannot join '{type_name}' with '{joined_type_name}' on column '{column_name}'")]
+pub struct InvalidJoinError {
     pub type_name: &'static str,
+    pub column_name: &'static str,
+    pub joined_type_name: &'static str,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -113,14 +310,41 @@ pub struct UnexpectedTypeError {
 
 #[derive(Error, Debug)]
 #[error(
-    "missing a column for {type_name}::{attr_name} \
+    "value {value} is out of range for {type_name}::{attr_name}: expected {expected_type:?} \
     (table: {table_name}, column: {column_name})"
 )]
-pub struct MissingColumnError {
+pub struct OutOfRangeError {
     pub type_name: &'static str,
     pub attr_name: &'static str,
     pub table_name: &'static str,
     pub column_name: &'static str,
+    pub expected_type: DataType,
+    pub value: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error(
+    "missing a column for {type_name}::{attr_name} \
+    (table: {table_name}, column: {column_name})"
+)]
+pub struct MissingColumnError {
+    pub type_name: &'static str,
+    /// The name of the missing field, or the column name for columns that
+    /// are not fields.
+    pub attr_name: String,
+    pub table_name: &'static str,
+    pub column_name: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("constraint violation: {constraint} (table: {table_name})")]
+pub struct ConstraintViolationError {
+    pub table_name: &'static str,
+    pub constraint: String,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
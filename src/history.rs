@@ -0,0 +1,173 @@
+use crate::{
+    data::{decode_values, encode_values, Timestamp, Value},
+    object::{Schema, Store},
+    storage::{Row, RowSlice},
+};
+
+use sha2::{Digest, Sha256};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The table recording the changes to `#[orm(audit)]` types, shared by all of
+/// them.
+pub(crate) const HISTORY_TABLE: &str = "orm_history";
+
+/// The kind of change recorded by a history entry.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+    /// A soft-deleted object was restored.
+    Restore,
+    /// A soft-deleted object was deleted for good.
+    Purge,
+}
+
+impl Operation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operation::Insert => "insert",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::Restore => "restore",
+            Operation::Purge => "purge",
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "insert" => Operation::Insert,
+            "update" => Operation::Update,
+            "delete" => Operation::Delete,
+            "restore" => Operation::Restore,
+            "purge" => Operation::Purge,
+            _ => return None,
+        })
+    }
+}
+
+/// A recorded change to an object of `T`, with the object as it was before
+/// and after the change. `before` is `None` for inserts and `after` for
+/// deletes.
+#[derive(Debug)]
+pub struct HistoryEntry<T> {
+    /// The position of the entry in the history table.
+    pub seq: u64,
+    pub operation: Operation,
+    pub before: Option<T>,
+    pub after: Option<T>,
+    /// The commit time of the transaction that made the change.
+    pub changed_at: Timestamp,
+    /// The actor set with `Transaction::set_actor`, if any.
+    pub actor: Option<String>,
+}
+
+/// The last record of the history table, returned by
+/// `Transaction::history_head`. The hash chain has no key: whoever can write
+/// to the database can rewrite the records along with their hashes, or drop
+/// the last ones. Storing the head outside the database, e.g. after each
+/// commit, and checking it with `verify_history_with` detects that.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HistoryHead {
+    pub seq: u64,
+    pub hash: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A row of the history table. Keys are encoded with `encode_values` and
+/// objects with `encode_snapshot`.
+pub(crate) struct HistoryRecord {
+    pub seq: u64,
+    pub object_type: String,
+    pub table_name: String,
+    pub object_key: String,
+    pub operation: Operation,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub changed_at: Timestamp,
+    pub actor: Option<String>,
+    pub hash: String,
+}
+
+impl HistoryRecord {
+    /// The SHA-256 of the record chained to `previous`, the hash of the record
+    /// before it or an empty string for the first one. Changing, inserting or
+    /// deleting a record breaks the chain from there on.
+    pub fn chain_hash(&self, previous: &str) -> String {
+        let changed_at = self.changed_at.as_micros().to_string();
+        let fields = [
+            Some(previous),
+            Some(self.object_type.as_str()),
+            Some(self.table_name.as_str()),
+            Some(self.object_key.as_str()),
+            Some(self.operation.as_str()),
+            self.before.as_deref(),
+            self.after.as_deref(),
+            Some(changed_at.as_str()),
+            self.actor.as_deref(),
+        ];
+        let mut hasher = Sha256::new();
+        for field in fields {
+            // Length prefixes keep the fields apart; `NULL` has its own.
+            match field {
+                Some(field) => {
+                    hasher.update((field.len() as u64).to_be_bytes());
+                    hasher.update(field.as_bytes());
+                }
+                None => hasher.update(u64::MAX.to_be_bytes()),
+            }
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Encodes the values of an object's row followed by each of its collections,
+/// as the number of rows and their values.
+pub(crate) fn encode_snapshot(row: &RowSlice, collections: &[Vec<Row>]) -> String {
+    let mut values = row.to_vec();
+    for rows in collections {
+        values.push(Value::Int64(rows.len() as i64));
+        values.extend(rows.iter().flatten().cloned());
+    }
+    encode_values(&values)
+}
+
+/// Encodes `obj` as `encode_snapshot` does.
+pub(crate) fn object_snapshot(obj: &dyn Store) -> String {
+    encode_snapshot(&obj.to_row(), &obj.collections_to_rows())
+}
+
+/// Decodes a snapshot of an object of `schema` into its row and collections,
+/// or `None` if `encoded` is not such a snapshot.
+pub(crate) fn decode_snapshot(
+    schema: &Schema,
+    encoded: &str,
+) -> Option<(Row<'static>, Vec<Vec<Row<'static>>>)> {
+    let mut values = decode_values(encoded)?.into_iter();
+    let mut take = |n: usize| {
+        let row = values.by_ref().take(n).collect::<Vec<_>>();
+        (row.len() == n).then_some(row)
+    };
+    let row = take(schema.fields.len())?;
+    let mut collections = Vec::with_capacity(schema.collections.len());
+    for collection in &schema.collections {
+        let count = match take(1)?.pop()? {
+            Value::Int64(count) => usize::try_from(count).ok()?,
+            _ => return None,
+        };
+        let rows = (0..count)
+            .map(|_| take(collection.schema.fields.len()))
+            .collect::<Option<Vec<_>>>()?;
+        collections.push(rows);
+    }
+    if take(1).is_some() {
+        return None;
+    }
+    Some((row, collections))
+}
@@ -1,17 +1,22 @@
 #![forbid(unsafe_code)]
 
+mod clock;
 mod connection;
 mod error;
+mod history;
 mod transaction;
 
 pub mod data;
 pub mod object;
+pub mod query;
 pub mod storage;
 
+pub use clock::{Clock, SystemClock};
 pub use connection::Connection;
-pub use data::ObjectId;
+pub use data::{ObjectId, Timestamp};
 pub use error::{Error, Result};
-pub use object::Object;
+pub use history::{HistoryEntry, HistoryHead, Operation};
+pub use object::{Audited, Embeddable, FromRow, Hooks, Object, PrimaryKey, Searchable, SoftDelete};
 pub use transaction::{ObjectState, Transaction, Tx};
 
-pub use orm_derive::Object;
+pub use orm_derive::{Embeddable, FromRow, Object};
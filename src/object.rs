@@ -1,13 +1,213 @@
-use crate::{data::DataType, storage::Row};
+use crate::{
+    data::{DataType, ObjectId, Timestamp, ToDataType},
+    storage::{Row, RowReader, Statements},
+    Result,
+};
 
-use std::any::Any;
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    sync::{Mutex, OnceLock},
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub trait Object: Any {
-    fn from_row(row: Row) -> Self;
-    fn to_row(&self) -> Row;
-    const SCHEMA: Schema;
+pub trait Object: Hooks + Any + Sized {
+    /// `ObjectId` unless fields are marked with `#[orm(primary_key)]`, in
+    /// which case it is the type of that field or a tuple of their types.
+    type Key: PrimaryKey;
+
+    fn from_row(row: Row, collections: Vec<Vec<Row>>) -> Result<Self>;
+    fn to_row(&self) -> Row<'_>;
+    fn collections_to_rows(&self) -> Vec<Vec<Row<'_>>>;
+    /// Returns the key of the object stored under `rowid`.
+    fn key(&self, rowid: ObjectId) -> Self::Key;
+    fn schema() -> &'static Schema;
+
+    /// Sets the `#[orm(updated_at)]` fields to `now`, and the
+    /// `#[orm(created_at)]` fields too if the object is being `inserted`.
+    #[allow(unused_variables)]
+    fn set_timestamps(&mut self, now: Timestamp, inserted: bool) {}
+}
+
+/// Callbacks run by the transaction around the storage of an object. The
+/// derive gives object types empty hooks unless the struct is marked with
+/// `#[orm(hooks)]`, in which case the type implements this trait itself.
+/// Returning an error, e.g. `Error::User`, fails the operation.
+pub trait Hooks {
+    /// Called by `Transaction::create` and `create_many` before the row is
+    /// inserted.
+    fn before_insert(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the object is decoded from storage.
+    fn after_load(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called on flush or commit before the changes of a modified object are
+    /// written.
+    fn before_update(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called on flush or commit before a removed object is deleted. Objects
+    /// deleted without being loaded, by `delete_by_id` or set-based deletes,
+    /// do not run it.
+    fn before_delete(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Implemented by the derive for types marked with `#[orm(soft_delete)]`,
+/// which `Transaction::restore` and `purge` require.
+pub trait SoftDelete: Object {}
+
+/// Implemented by the derive for types marked with `#[orm(audit)]`, which
+/// `Transaction::history`, `get_as_of` and `Query::fetch_as_of` require.
+pub trait Audited: Object {}
+
+/// Implemented by the derive for types with `#[orm(fulltext)]` fields, which
+/// `Transaction::search` requires.
+pub trait Searchable: Object {}
+
+/// The primary key of an object, stored as one value per key column.
+pub trait PrimaryKey: Clone + Eq + Hash + Debug + 'static {
+    fn to_values(&self) -> Row<'_>;
+}
+
+impl PrimaryKey for ObjectId {
+    fn to_values(&self) -> Row<'_> {
+        vec![self.into()]
+    }
+}
+
+macro_rules! impl_primitive_key {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl PrimaryKey for $ty {
+                fn to_values(&self) -> Row<'_> {
+                    vec![self.into()]
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive_key!(String, Vec<u8>, i8, i16, i32, i64, u8, u16, u32, u64, bool);
+
+macro_rules! impl_composite_key {
+    ($($name:ident),*) => {
+        impl<$($name: PrimaryKey),*> PrimaryKey for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn to_values(&self) -> Row<'_> {
+                let ($($name,)*) = self;
+                let mut values = Vec::new();
+                $(values.extend($name.to_values());)*
+                values
+            }
+        }
+    };
+}
+
+impl_composite_key!(A, B);
+impl_composite_key!(A, B, C);
+impl_composite_key!(A, B, C, D);
+
+/// A read-only shape of a query result, decoded from columns matched by name.
+/// Unlike objects, rows are neither tracked by a transaction nor written back.
+pub trait FromRow: Sized {
+    /// Describes the columns read; the table name is the name of the type.
+    fn schema() -> &'static Schema;
+    fn from_row(row: Row) -> Result<Self>;
+}
+
+/// A value object whose fields are stored inline in the table of every object
+/// embedding it with `#[flatten]`.
+pub trait Embeddable: Sized {
+    fn fields() -> &'static [Field];
+    fn read(reader: &mut RowReader<'_, '_>) -> Result<Self>;
+    fn write<'a>(&'a self, row: &mut Row<'a>);
+}
+
+/// An item of a `Vec` field. Primitive items take a single `value` column
+/// with an empty attribute name, embeddable items take the columns of their
+/// fields.
+pub trait Element: Sized {
+    fn fields() -> &'static [Field];
+    fn read(reader: &mut RowReader<'_, '_>) -> Result<Self>;
+    fn write<'a>(&'a self, row: &mut Row<'a>);
+}
+
+impl<E: Embeddable> Element for E {
+    fn fields() -> &'static [Field] {
+        E::fields()
+    }
+
+    fn read(reader: &mut RowReader<'_, '_>) -> Result<Self> {
+        E::read(reader)
+    }
+
+    fn write<'a>(&'a self, row: &mut Row<'a>) {
+        E::write(self, row)
+    }
+}
+
+macro_rules! impl_primitive_element {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl Element for $ty {
+                fn fields() -> &'static [Field] {
+                    const FIELDS: &[Field] = &[Field {
+                        column_name: "value",
+                        data_type: <$ty as ToDataType>::DATA_TYPE,
+                        attr_name: "",
+                        default: None,
+                    }];
+                    FIELDS
+                }
+
+                fn read(reader: &mut RowReader<'_, '_>) -> Result<Self> {
+                    reader.read()
+                }
+
+                fn write<'a>(&'a self, row: &mut Row<'a>) {
+                    row.push(self.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive_element!(
+    String,
+    Vec<u8>,
+    i8,
+    i16,
+    i32,
+    i64,
+    u8,
+    u16,
+    u32,
+    u64,
+    f32,
+    f64,
+    bool
+);
+
+/// Encodes the items of a `Vec` field as rows of its collection table.
+pub fn element_rows<E: Element>(items: &[E]) -> Vec<Row<'_>> {
+    items
+        .iter()
+        .map(|item| {
+            let mut row = Vec::new();
+            item.write(&mut row);
+            row
+        })
+        .collect()
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -16,42 +216,224 @@ pub struct Field {
     pub column_name: &'static str,
     pub data_type: DataType,
     pub attr_name: &'static str,
+    /// SQL literal used as the column's `DEFAULT`, only affects the DDL.
+    pub default: Option<&'static str>,
 }
 
 impl Field {
     pub fn get_create_sql(&self) -> String {
-        format!(
-            "{} {}",
-            self.column_name,
-            match self.data_type {
-                DataType::String => "TEXT",
-                DataType::Bytes => "BLOB",
-                DataType::Int64 => "BIGINT",
-                DataType::Float64 => "REAL",
-                DataType::Bool => "TINYINT",
-            }
-        )
+        let mut sql = format!("{} {}", self.column_name, self.data_type.sql_type());
+        if let Some(default) = self.default {
+            sql.push_str(" DEFAULT ");
+            sql.push_str(default);
+        }
+        sql
     }
 }
 
 pub struct Schema {
     pub table_name: &'static str,
-    pub fields: &'static [Field],
+    pub fields: Vec<Field>,
+    /// Indices into `fields` of the `#[orm(primary_key)]` fields. Empty for
+    /// tables keyed by an implicit `id` column.
+    pub primary_key: Vec<usize>,
+    /// Indices into `fields` of the `#[orm(fulltext)]` fields, indexed in an
+    /// FTS5 table alongside the table. Only tables keyed by an implicit `id`
+    /// have them.
+    pub fulltext: Vec<usize>,
+    pub collections: Vec<Collection>,
+    pub indexes: Vec<Index>,
+    pub checks: Vec<Check>,
+    pub foreign_keys: Vec<ForeignKey>,
+    /// Whether deleted rows are kept and marked in a `deleted_at` column,
+    /// from `#[orm(soft_delete)]` on the struct.
+    pub soft_delete: bool,
+    /// Whether changes are recorded in the history table, from
+    /// `#[orm(audit)]` on the struct.
+    pub audit: bool,
     pub type_name: &'static str,
+    #[doc(hidden)]
+    pub statements: Statements,
+}
+
+impl Schema {
+    /// Names and types of the columns the table is keyed by.
+    pub fn key_columns(&self) -> Vec<(&'static str, DataType)> {
+        if self.primary_key.is_empty() {
+            vec![("id", DataType::Int64)]
+        } else {
+            self.primary_key
+                .iter()
+                .map(|&i| (self.fields[i].column_name, self.fields[i].data_type))
+                .collect()
+        }
+    }
+}
+
+/// An index over columns of the table, declared with `#[orm(index)]` or
+/// `#[orm(unique)]` on a field or `#[orm(index(a, b))]` on the struct.
+pub struct Index {
+    pub name: &'static str,
+    pub columns: Vec<&'static str>,
+    pub unique: bool,
+}
+
+impl Index {
+    pub fn get_create_sql(&self, table_name: &str) -> String {
+        format!(
+            "CREATE {}INDEX {} ON {}({})",
+            if self.unique { "UNIQUE " } else { "" },
+            self.name,
+            table_name,
+            self.columns.join(",")
+        )
+    }
+}
+
+/// A `CHECK` constraint declared with `#[orm(check = "...")]`.
+pub struct Check {
+    pub name: &'static str,
+    pub expr: &'static str,
+}
+
+impl Check {
+    pub fn get_create_sql(&self) -> String {
+        format!("CONSTRAINT {} CHECK({})", self.name, self.expr)
+    }
+}
+
+/// A `FOREIGN KEY` constraint declared with `#[orm(references = Type)]` on a
+/// field holding keys of `Type`. Referenced rows cannot be deleted, though
+/// objects of `#[orm(soft_delete)]` types can still be marked as deleted.
+pub struct ForeignKey {
+    pub column_name: &'static str,
+    /// The schema of the referenced type, looked up lazily since types may
+    /// reference each other.
+    pub references: fn() -> &'static Schema,
+}
+
+impl ForeignKey {
+    pub fn get_create_sql(&self) -> String {
+        let references = (self.references)();
+        let key_columns = references
+            .key_columns()
+            .into_iter()
+            .map(|(c, _)| c)
+            .collect::<Vec<_>>();
+        format!(
+            "FOREIGN KEY({}) REFERENCES {}({})",
+            self.column_name,
+            references.table_name,
+            key_columns.join(",")
+        )
+    }
 }
 
+/// A `Vec` field stored in a child table keyed by the owner id and the item
+/// position. `schema` describes the child table and its item columns.
+pub struct Collection {
+    pub attr_name: &'static str,
+    pub schema: Schema,
+}
+
+impl Collection {
+    pub fn new<E: Element>(
+        owner_table: &str,
+        column_name: &str,
+        attr_name: &'static str,
+        type_name: &'static str,
+    ) -> Self {
+        let fields = E::fields()
+            .iter()
+            .map(|f| Field {
+                column_name: f.column_name,
+                data_type: f.data_type,
+                attr_name: if f.attr_name.is_empty() {
+                    attr_name
+                } else {
+                    Box::leak(format!("{}.{}", attr_name, f.attr_name).into_boxed_str())
+                },
+                default: f.default,
+            })
+            .collect();
+        Self {
+            attr_name,
+            schema: Schema {
+                table_name: Box::leak(format!("{}_{}", owner_table, column_name).into_boxed_str()),
+                fields,
+                primary_key: Vec::new(),
+                fulltext: Vec::new(),
+                collections: Vec::new(),
+                indexes: Vec::new(),
+                checks: Vec::new(),
+                foreign_keys: Vec::new(),
+                soft_delete: false,
+                audit: false,
+                type_name,
+                statements: Statements::default(),
+            },
+        }
+    }
+}
+
+/// Appends the fields of `E` to `fields`, prefixing column names with
+/// `column_prefix_` and attribute names with `attr_prefix.`.
+pub fn push_embedded_fields<E: Embeddable>(
+    fields: &mut Vec<Field>,
+    column_prefix: &str,
+    attr_prefix: &str,
+) {
+    // Schemas are built once per type, so leaking the joined names is bounded.
+    fields.extend(E::fields().iter().map(|f| Field {
+        column_name: Box::leak(format!("{}_{}", column_prefix, f.column_name).into_boxed_str()),
+        data_type: f.data_type,
+        attr_name: Box::leak(format!("{}.{}", attr_prefix, f.attr_name).into_boxed_str()),
+        default: f.default,
+    }));
+}
+
+/// Returns a value built once per `(T, V)` pair. Statics cannot depend on
+/// generic parameters, so the derives fall back to this for generic types.
+#[doc(hidden)]
+pub fn generic_static<T: Any, V: Any + Send + Sync>(init: impl FnOnce() -> V) -> &'static V {
+    type Registry = Mutex<HashMap<TypeId, &'static (dyn Any + Send + Sync)>>;
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+    let registry = REGISTRY.get_or_init(Registry::default);
+    let key = TypeId::of::<(T, V)>();
+    if let Some(value) = registry.lock().unwrap().get(&key) {
+        return value.downcast_ref().unwrap();
+    }
+    // `init` runs without the lock, since building a schema may build those
+    // of the generic types it embeds. If another thread got there first, its
+    // value is kept.
+    let value: &'static (dyn Any + Send + Sync) = Box::leak(Box::new(init()));
+    let value = *registry.lock().unwrap().entry(key).or_insert(value);
+    value.downcast_ref().unwrap()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub trait Store: Any {
-    fn to_row(&self) -> Row;
+    fn to_row(&self) -> Row<'_>;
+    fn collections_to_rows(&self) -> Vec<Vec<Row<'_>>>;
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
     fn get_schema(&self) -> &'static Schema;
+    fn before_update(&mut self) -> Result<()>;
+    fn before_delete(&self) -> Result<()>;
+    fn set_timestamps(&mut self, now: Timestamp, inserted: bool);
 }
 
 impl<T: Object> Store for T {
-    fn to_row(&self) -> Row {
+    fn to_row(&self) -> Row<'_> {
         T::to_row(self)
     }
 
+    fn collections_to_rows(&self) -> Vec<Vec<Row<'_>>> {
+        T::collections_to_rows(self)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -61,6 +443,18 @@ impl<T: Object> Store for T {
     }
 
     fn get_schema(&self) -> &'static Schema {
-        &T::SCHEMA
+        T::schema()
+    }
+
+    fn before_update(&mut self) -> Result<()> {
+        Hooks::before_update(self)
+    }
+
+    fn before_delete(&self) -> Result<()> {
+        Hooks::before_delete(self)
+    }
+
+    fn set_timestamps(&mut self, now: Timestamp, inserted: bool) {
+        T::set_timestamps(self, now, inserted)
     }
 }
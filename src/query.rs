@@ -0,0 +1,1090 @@
+use crate::{
+    data::{decode_values, encode_values, DataType, Timestamp, ToDataType, Value, ValueError},
+    error::{invalid_join_error, map_value_error, null_value_error},
+    object::{Audited, FromRow, Object, Schema},
+    storage::{live_condition, Row, RowSlice},
+    Error, ObjectId, Result, Transaction, Tx,
+};
+
+use std::{borrow::Borrow, marker::PhantomData, ops::Not};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A typed handle to a column of `T` holding values of type `V`. The derive
+/// generates one per plain field, reachable through `T::columns()`.
+pub struct Column<T, V> {
+    name: &'static str,
+    marker: PhantomData<fn() -> (T, V)>,
+}
+
+impl<T, V> Column<T, V> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T, V> Clone for Column<T, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, V> Copy for Column<T, V> {}
+
+impl<T: Object, V> Column<T, V> {
+    pub fn eq(self, value: &(impl ValueOf<V> + ?Sized)) -> Filter<T> {
+        self.compare("=", value.to_value())
+    }
+
+    pub fn ne(self, value: &(impl ValueOf<V> + ?Sized)) -> Filter<T> {
+        self.compare("<>", value.to_value())
+    }
+
+    pub fn lt(self, value: &(impl ValueOf<V> + ?Sized)) -> Filter<T> {
+        self.compare("<", value.to_value())
+    }
+
+    pub fn le(self, value: &(impl ValueOf<V> + ?Sized)) -> Filter<T> {
+        self.compare("<=", value.to_value())
+    }
+
+    pub fn gt(self, value: &(impl ValueOf<V> + ?Sized)) -> Filter<T> {
+        self.compare(">", value.to_value())
+    }
+
+    pub fn ge(self, value: &(impl ValueOf<V> + ?Sized)) -> Filter<T> {
+        self.compare(">=", value.to_value())
+    }
+
+    fn compare(self, operator: &str, value: Value) -> Filter<T> {
+        Filter::new(
+            format!("{}.{} {} ?", T::schema().table_name, self.name, operator),
+            vec![value.into_owned()],
+        )
+    }
+}
+
+/// Values a column holding `V` can be compared to or set to: `V` itself or a
+/// borrowed form of it, such as `str` for `String`.
+pub trait ValueOf<V> {
+    fn to_value(&self) -> Value<'_>;
+}
+
+impl<V, Q> ValueOf<V> for Q
+where
+    V: Borrow<Q>,
+    Q: ?Sized,
+    for<'v> Value<'v>: From<&'v Q>,
+{
+    fn to_value(&self) -> Value<'_> {
+        self.into()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A condition on the columns of `T`, built by comparing a `Column` to a
+/// value and combined with `and`, `or` and `!`.
+pub struct Filter<T> {
+    sql: String,
+    parameters: Row<'static>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Filter<T> {
+    fn new(sql: String, parameters: Row<'static>) -> Self {
+        Self {
+            sql,
+            parameters,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn and(self, other: Filter<T>) -> Self {
+        self.combine("AND", other)
+    }
+
+    pub fn or(self, other: Filter<T>) -> Self {
+        self.combine("OR", other)
+    }
+
+    fn combine(mut self, operator: &str, other: Filter<T>) -> Self {
+        self.parameters.extend(other.parameters);
+        Self::new(
+            format!("({}) {} ({})", self.sql, operator, other.sql),
+            self.parameters,
+        )
+    }
+
+    pub(crate) fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    pub(crate) fn parameters(&self) -> &RowSlice<'static> {
+        &self.parameters
+    }
+}
+
+impl<T> Not for Filter<T> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self::new(format!("NOT ({})", self.sql), self.parameters)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Set-based update of the objects of `T` matching a filter, returned by
+/// `Transaction::update`. It can be executed once a column is `set`, which
+/// `S` tracks.
+pub struct Update<'t, 'a, T: Object, S = Unset> {
+    tx: &'t Transaction<'a>,
+    filter: Option<Filter<T>>,
+    indices: Vec<usize>,
+    values: Row<'static>,
+    marker: PhantomData<S>,
+}
+
+/// An `Update` without any column to set.
+pub struct Unset;
+
+/// An `Update` with at least one column to set.
+pub struct Set;
+
+impl<'t, 'a, T: Object> Update<'t, 'a, T> {
+    pub(crate) fn new(tx: &'t Transaction<'a>) -> Self {
+        Self {
+            tx,
+            filter: None,
+            indices: Vec::new(),
+            values: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'t, 'a, T: Object, S> Update<'t, 'a, T, S> {
+    /// Restricts the update to objects matching `filter`, in addition to any
+    /// filter given before.
+    pub fn filter(mut self, filter: Filter<T>) -> Self {
+        self.filter = Some(match self.filter {
+            Some(current) => current.and(filter),
+            None => filter,
+        });
+        self
+    }
+
+    pub fn set<V>(
+        mut self,
+        column: Column<T, V>,
+        value: &(impl ValueOf<V> + ?Sized),
+    ) -> Update<'t, 'a, T, Set> {
+        let schema = T::schema();
+        let index = field_index(schema, column.name);
+        assert!(
+            !schema.primary_key.contains(&index),
+            "cannot update primary key column '{}'",
+            column.name
+        );
+        self.indices.push(index);
+        self.values.push(value.to_value().into_owned());
+        Update {
+            tx: self.tx,
+            filter: self.filter,
+            indices: self.indices,
+            values: self.values,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'t, 'a, T: Object> Update<'t, 'a, T, Set> {
+    /// Runs the update and returns the number of affected objects. Pending
+    /// changes are flushed first and cached objects are reloaded afterwards.
+    /// Fails with `ObjectBorrowed`, before changing anything, if one of them
+    /// is still borrowed.
+    pub fn execute(self) -> Result<usize> {
+        self.tx
+            .update_where(&self.indices, &self.values, self.filter.as_ref())
+    }
+}
+
+/// Set-based delete of the objects of `T` matching a filter, returned by
+/// `Transaction::delete`.
+pub struct Delete<'t, 'a, T: Object> {
+    tx: &'t Transaction<'a>,
+    filter: Option<Filter<T>>,
+}
+
+impl<'t, 'a, T: Object> Delete<'t, 'a, T> {
+    pub(crate) fn new(tx: &'t Transaction<'a>) -> Self {
+        Self { tx, filter: None }
+    }
+
+    /// Restricts the delete to objects matching `filter`, in addition to any
+    /// filter given before.
+    pub fn filter(mut self, filter: Filter<T>) -> Self {
+        self.filter = Some(match self.filter {
+            Some(current) => current.and(filter),
+            None => filter,
+        });
+        self
+    }
+
+    /// Runs the delete and returns the number of deleted objects. Pending
+    /// changes are flushed first and cached objects are marked as removed.
+    /// Fails with `ObjectBorrowed`, before deleting anything, if one of them
+    /// is still borrowed.
+    pub fn execute(self) -> Result<usize> {
+        self.tx.delete_where(self.filter.as_ref())
+    }
+}
+
+fn field_index(schema: &Schema, column_name: &str) -> usize {
+    schema
+        .fields
+        .iter()
+        .position(|f| f.column_name == column_name)
+        .unwrap_or_else(|| panic!("unknown column '{}'", column_name))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Types a column can hold, decoded from the values of aggregate queries.
+pub trait ColumnValue: ToDataType + for<'v> TryFrom<Value<'v>, Error = ValueError> {}
+
+impl<V> ColumnValue for V where V: ToDataType + for<'v> TryFrom<Value<'v>, Error = ValueError> {}
+
+/// Column types that can be summed. Integers are summed as `i64` and floating
+/// point numbers as `f64`, so that a sum is not limited to the column type.
+pub trait Summable: ColumnValue {
+    type Sum: ColumnValue;
+}
+
+macro_rules! impl_summable {
+    ($sum:ty => $($ty:ty),*) => {
+        $(
+            impl Summable for $ty {
+                type Sum = $sum;
+            }
+        )*
+    };
+}
+
+impl_summable!(i64 => i8, i16, i32, i64, u8, u16, u32, u64);
+impl_summable!(f64 => f32, f64);
+
+/// The `WHERE`, `GROUP BY`, `ORDER BY` and `LIMIT` clauses of a query.
+#[derive(Clone, Default)]
+pub(crate) struct Clauses {
+    filter: Option<String>,
+    parameters: Row<'static>,
+    group_by: Option<&'static str>,
+    /// Table, column and whether the order is descending.
+    order_by: Vec<(&'static str, &'static str, bool)>,
+    limit: Option<u64>,
+    offset: u64,
+    /// Whether soft-deleted rows are included.
+    include_deleted: bool,
+}
+
+impl Clauses {
+    fn filter<T>(&mut self, filter: Filter<T>) {
+        self.and_where(filter.sql, filter.parameters);
+    }
+
+    fn and_where(&mut self, sql: String, parameters: Row<'static>) {
+        self.filter = Some(match self.filter.take() {
+            Some(current) => format!("({}) AND ({})", current, sql),
+            None => sql,
+        });
+        self.parameters.extend(parameters);
+    }
+
+    /// Makes the order total by breaking ties on the rowid, as keyset
+    /// iteration requires.
+    fn order_by_rowid(&mut self, schema: &'static Schema) {
+        if !self.order_by.iter().any(|(_, c, _)| *c == "rowid") {
+            self.order_by.push((schema.table_name, "rowid", false));
+        }
+    }
+
+    fn order_by<T: Object, V>(&mut self, column: Column<T, V>, desc: bool) {
+        self.order_by
+            .push((T::schema().table_name, column.name, desc));
+    }
+
+    /// The values of the ordering columns of `row`, stored under `rowid`.
+    fn ordering_values(&self, schema: &Schema, rowid: ObjectId, row: &RowSlice) -> Row<'static> {
+        self.order_by
+            .iter()
+            .map(|(_, c, _)| match *c {
+                "rowid" => Value::from(&rowid).into_owned(),
+                c => row[field_index(schema, c)].clone().into_owned(),
+            })
+            .collect()
+    }
+
+    /// Restricts the clauses to the rows ordered after the row whose ordering
+    /// columns hold `values`.
+    fn after(&mut self, values: &RowSlice<'static>) {
+        let mut terms = Vec::new();
+        let mut parameters = Vec::new();
+        for (i, (table, column, desc)) in self.order_by.iter().enumerate() {
+            let mut term = self.order_by[..i]
+                .iter()
+                .map(|(t, c, _)| format!("{}.{} = ?", t, c))
+                .collect::<Vec<_>>();
+            term.push(format!(
+                "{}.{} {} ?",
+                table,
+                column,
+                if *desc { "<" } else { ">" }
+            ));
+            terms.push(term.join(" AND "));
+            parameters.extend(values[..=i].iter().cloned());
+        }
+        self.and_where(format!("({})", terms.join(") OR (")), parameters);
+    }
+
+    /// Clauses selecting the rows matching the SQL `filter`, as a set-based
+    /// statement run with it would.
+    pub(crate) fn matching(filter: &str, parameters: &RowSlice, include_deleted: bool) -> Self {
+        Clauses {
+            filter: Some(filter.to_string()),
+            parameters: parameters.iter().map(|v| v.clone().into_owned()).collect(),
+            include_deleted,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn parameters(&self) -> &RowSlice<'static> {
+        &self.parameters
+    }
+
+    pub(crate) fn include_deleted(&self) -> bool {
+        self.include_deleted
+    }
+
+    pub(crate) fn to_sql(&self, schema: &Schema) -> String {
+        let mut sql = String::new();
+        let filter = match (&self.filter, schema.soft_delete && !self.include_deleted) {
+            (Some(filter), true) => Some(live_condition(schema, filter)),
+            (Some(filter), false) => Some(filter.clone()),
+            (None, true) => Some(live_condition(schema, "1")),
+            (None, false) => None,
+        };
+        if let Some(filter) = filter {
+            sql.push_str(&format!(" WHERE {}", filter));
+        }
+        if let Some(column) = self.group_by {
+            sql.push_str(&format!(" GROUP BY {}", column));
+        }
+        if !self.order_by.is_empty() {
+            let order_by = self
+                .order_by
+                .iter()
+                .map(|(t, c, desc)| format!("{}.{} {}", t, c, if *desc { "DESC" } else { "ASC" }))
+                .collect::<Vec<_>>()
+                .join(",");
+            sql.push_str(&format!(" ORDER BY {}", order_by));
+        }
+        if self.limit.is_some() || self.offset > 0 {
+            let limit = self.limit.map_or(-1, |l| l as i64);
+            sql.push_str(&format!(" LIMIT {} OFFSET {}", limit, self.offset));
+        }
+        sql
+    }
+}
+
+/// A query over the objects of `T`, returned by `Transaction::query`. Queries
+/// flush the pending changes of the transaction before they run, so their
+/// results include them.
+pub struct Query<'t, 'a, T: Object> {
+    tx: &'t Transaction<'a>,
+    clauses: Clauses,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<'t, 'a, T: Object> Query<'t, 'a, T> {
+    pub(crate) fn new(tx: &'t Transaction<'a>) -> Self {
+        Self {
+            tx,
+            clauses: Clauses::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Restricts the query to objects matching `filter`, in addition to any
+    /// filter given before.
+    pub fn filter(mut self, filter: Filter<T>) -> Self {
+        self.clauses.filter(filter);
+        self
+    }
+
+    pub fn order_by<V>(mut self, column: Column<T, V>) -> Self {
+        self.clauses.order_by(column, false);
+        self
+    }
+
+    pub fn order_by_desc<V>(mut self, column: Column<T, V>) -> Self {
+        self.clauses.order_by(column, true);
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.clauses.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.clauses.offset = offset;
+        self
+    }
+
+    /// Includes objects of `#[orm(soft_delete)]` types that are marked as
+    /// deleted, which are left out by default.
+    pub fn with_deleted(mut self) -> Self {
+        self.clauses.include_deleted = true;
+        self
+    }
+
+    /// Loads the matching objects through the identity map.
+    pub fn fetch(self) -> Result<Vec<Tx<'t, T>>> {
+        self.tx.select_where(&self.clauses)
+    }
+
+    /// Fetches the matching objects as they were at `at`, rebuilt from the
+    /// history of an `#[orm(audit)]` type, e.g. to reproduce a report as of
+    /// the end of a month. The objects are not tracked by the transaction.
+    pub fn fetch_as_of(self, at: Timestamp) -> Result<Vec<T>>
+    where
+        T: Audited,
+    {
+        self.tx.select_as_of(&self.clauses, at)
+    }
+
+    /// Selects only the given columns of the matching objects: a `Column` or
+    /// a tuple of them, decoded into values or a tuple of values. The values
+    /// are not tracked by the transaction.
+    pub fn select<P: Projection<T>>(self, projection: P) -> Result<Vec<P::Output>> {
+        let mut columns = Vec::new();
+        projection.push_columns(&mut columns);
+        self.tx
+            .select_values::<T>(&columns, &self.clauses)?
+            .into_iter()
+            .map(|row| projection.decode(&mut row.into_iter()))
+            .collect()
+    }
+
+    /// Selects the columns of `R` from the matching objects, matched to the
+    /// fields of `R` by name.
+    pub fn select_as<R: FromRow>(self) -> Result<Vec<R>> {
+        let schema = R::schema();
+        let columns = schema
+            .fields
+            .iter()
+            .map(|f| (f.column_name.to_string(), f.data_type))
+            .collect::<Vec<_>>();
+        self.tx
+            .select_values::<T>(&columns, &self.clauses)?
+            .into_iter()
+            .map(|row| {
+                let row = row
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, v)| v.ok_or_else(|| null_error(schema, i)))
+                    .collect::<Result<_>>()?;
+                R::from_row(row)
+            })
+            .collect()
+    }
+
+    /// Iterates over the matching objects, loading them through the identity
+    /// map a chunk of rows at a time.
+    pub fn iter(self) -> Result<Iter<'t, 'a, T>> {
+        Ok(Iter {
+            chunks: self.chunks()?,
+        })
+    }
+
+    /// Like `iter`, but decodes objects without adding them to the identity
+    /// map, so that memory stays bounded on large read-only scans. Changes to
+    /// the returned objects are not saved.
+    pub fn iter_detached(self) -> Result<DetachedIter<'t, 'a, T>> {
+        Ok(DetachedIter {
+            chunks: self.chunks()?,
+        })
+    }
+
+    /// Splits the matching objects into pages of `page_size` objects, read by
+    /// offset with `Paginate::page` or by cursor with `Paginate::after`, which
+    /// fail with `InvalidPageSize` if `page_size` is zero.
+    pub fn paginate(self, page_size: u64) -> Paginate<'t, 'a, T> {
+        Paginate {
+            query: self,
+            page_size,
+        }
+    }
+
+    fn chunks(mut self) -> Result<Chunks<'t, 'a, T>> {
+        self.tx.begin_query::<T>()?;
+        self.clauses.order_by_rowid(T::schema());
+        let remaining = self.clauses.limit.take();
+        let offset = std::mem::take(&mut self.clauses.offset);
+        Ok(Chunks {
+            tx: self.tx,
+            clauses: self.clauses,
+            last: None,
+            offset,
+            remaining,
+            rows: Vec::new().into_iter(),
+            done: false,
+            marker: PhantomData,
+        })
+    }
+
+    /// The number of matching objects. Like the other aggregates, it ignores
+    /// `limit` and `offset`.
+    pub fn count(self) -> Result<u64> {
+        let value = self.aggregate("COUNT(*)".to_string(), DataType::Int64)?;
+        Ok(i64::try_from(value.unwrap()).unwrap() as u64)
+    }
+
+    /// The sum of `column` over the matching objects, zero if there are none.
+    pub fn sum<V: Summable>(self, column: Column<T, V>) -> Result<V::Sum> {
+        let value = self.aggregate(
+            format!("COALESCE(SUM({}), 0)", column.name),
+            V::Sum::DATA_TYPE,
+        )?;
+        decode(column, value.unwrap())
+    }
+
+    pub fn min<V: ColumnValue>(self, column: Column<T, V>) -> Result<Option<V>> {
+        let value = self.aggregate(format!("MIN({})", column.name), V::DATA_TYPE)?;
+        value.map(|v| decode(column, v)).transpose()
+    }
+
+    pub fn max<V: ColumnValue>(self, column: Column<T, V>) -> Result<Option<V>> {
+        let value = self.aggregate(format!("MAX({})", column.name), V::DATA_TYPE)?;
+        value.map(|v| decode(column, v)).transpose()
+    }
+
+    pub fn avg<V>(self, column: Column<T, V>) -> Result<Option<f64>> {
+        let value = self.aggregate(format!("AVG({})", column.name), DataType::Float64)?;
+        Ok(value.map(|v| f64::try_from(v).unwrap()))
+    }
+
+    /// Pairs each matching object with the object of `U` its `column` refers
+    /// to, skipping objects without one. The column must be declared with
+    /// `#[orm(references = U)]`, or fetching fails with `InvalidJoin`.
+    pub fn join<U: Object>(self, column: Column<T, U::Key>) -> Join<'t, 'a, T, U, Inner> {
+        Join::new(self, column.name)
+    }
+
+    /// Like `join`, but keeps objects without a match, paired with `None`.
+    pub fn left_join<U: Object>(self, column: Column<T, U::Key>) -> Join<'t, 'a, T, U, Left> {
+        Join::new(self, column.name)
+    }
+
+    /// Groups the matching objects by `column`; aggregates of the grouped
+    /// query return one `(key, value)` pair per group, ordered by key.
+    pub fn group_by<K: ColumnValue>(mut self, column: Column<T, K>) -> GroupBy<'t, 'a, T, K> {
+        self.clauses.group_by = Some(column.name);
+        if self.clauses.order_by.is_empty() {
+            self.clauses.order_by(column, false);
+        }
+        GroupBy {
+            query: self,
+            column,
+        }
+    }
+
+    fn aggregate(
+        mut self,
+        expression: String,
+        data_type: DataType,
+    ) -> Result<Option<Value<'static>>> {
+        // A single aggregate row is computed over all matching objects.
+        self.clauses.limit = None;
+        self.clauses.offset = 0;
+        self.clauses.order_by.clear();
+        let mut rows = self
+            .tx
+            .select_values::<T>(&[(expression, data_type)], &self.clauses)?;
+        Ok(rows.pop().and_then(|mut row| row.pop().unwrap()))
+    }
+}
+
+/// Rows of a query fetched `CHUNK_SIZE` at a time. Each chunk starts after the
+/// last row of the previous one in the query order, so that rows are not
+/// skipped by offset.
+struct Chunks<'t, 'a, T> {
+    tx: &'t Transaction<'a>,
+    clauses: Clauses,
+    /// Ordering values of the last row returned.
+    last: Option<Row<'static>>,
+    offset: u64,
+    remaining: Option<u64>,
+    rows: std::vec::IntoIter<(ObjectId, Row<'static>)>,
+    done: bool,
+    marker: PhantomData<fn() -> T>,
+}
+
+const CHUNK_SIZE: u64 = 1024;
+
+impl<'t, 'a, T: Object> Chunks<'t, 'a, T> {
+    fn next_row(&mut self) -> Result<Option<(ObjectId, Row<'static>)>> {
+        loop {
+            if self.remaining == Some(0) {
+                return Ok(None);
+            }
+            if let Some((rowid, row)) = self.rows.next() {
+                self.last = Some(self.clauses.ordering_values(T::schema(), rowid, &row));
+                self.remaining = self.remaining.map(|r| r - 1);
+                return Ok(Some((rowid, row)));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            let size = self.remaining.map_or(CHUNK_SIZE, |r| r.min(CHUNK_SIZE));
+            let mut clauses = self.clauses.clone();
+            match &self.last {
+                Some(last) => clauses.after(last),
+                None => clauses.offset = self.offset,
+            }
+            clauses.limit = Some(size);
+            let rows = self.tx.select_rows::<T>(&clauses).inspect_err(|_| {
+                self.done = true;
+            })?;
+            self.done = (rows.len() as u64) < size;
+            self.rows = rows.into_iter();
+        }
+    }
+}
+
+/// Iterator over the objects of a query, returned by `Query::iter`.
+pub struct Iter<'t, 'a, T: Object> {
+    chunks: Chunks<'t, 'a, T>,
+}
+
+impl<'t, 'a, T: Object> Iterator for Iter<'t, 'a, T> {
+    type Item = Result<Tx<'t, T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.chunks.next_row() {
+                Ok(Some((rowid, row))) => {
+                    let include_deleted = self.chunks.clauses.include_deleted();
+                    match self.chunks.tx.resolve(rowid, row, include_deleted) {
+                        Ok(Some(tx)) => return Some(Ok(tx)),
+                        Ok(None) => continue,
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Iterator over detached objects of a query, returned by
+/// `Query::iter_detached`.
+pub struct DetachedIter<'t, 'a, T: Object> {
+    chunks: Chunks<'t, 'a, T>,
+}
+
+impl<'t, 'a, T: Object> Iterator for DetachedIter<'t, 'a, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks
+            .next_row()
+            .transpose()
+            .map(|row| row.and_then(|(rowid, row)| self.chunks.tx.read_detached(rowid, row)))
+    }
+}
+
+/// A query split into pages, returned by `Query::paginate`. Any limit or
+/// offset set on the query is ignored.
+pub struct Paginate<'t, 'a, T: Object> {
+    query: Query<'t, 'a, T>,
+    page_size: u64,
+}
+
+/// A page of objects read by offset, along with the total number of objects.
+pub struct Page<'t, T: Object> {
+    pub items: Vec<Tx<'t, T>>,
+    /// Zero-based index of the page.
+    pub page: u64,
+    pub page_size: u64,
+    pub total: u64,
+}
+
+impl<'t, T: Object> Page<'t, T> {
+    pub fn page_count(&self) -> u64 {
+        self.total.div_ceil(self.page_size)
+    }
+}
+
+/// A page of objects read by cursor. `next_cursor` is `None` on the last page.
+pub struct KeysetPage<'t, T: Object> {
+    pub items: Vec<Tx<'t, T>>,
+    pub next_cursor: Option<String>,
+}
+
+/// An object matching a full-text search, returned by `Transaction::search`.
+pub struct SearchHit<'t, T: Object> {
+    pub item: Tx<'t, T>,
+    /// An excerpt of the best matching field, with matched terms enclosed in
+    /// square brackets.
+    pub snippet: String,
+    /// The BM25 rank of the match; lower is better.
+    pub rank: f64,
+}
+
+impl<'t, 'a, T: Object> Paginate<'t, 'a, T> {
+    /// Returns the page with the zero-based index `page`. Deep pages get slow
+    /// as the storage skips the rows before them; prefer `after` for those.
+    pub fn page(self, page: u64) -> Result<Page<'t, T>> {
+        if self.page_size == 0 {
+            return Err(Error::InvalidPageSize);
+        }
+        let Query { tx, clauses, .. } = self.query;
+        let mut count_clauses = clauses.clone();
+        count_clauses.order_by.clear();
+        count_clauses.limit = None;
+        count_clauses.offset = 0;
+        let total = tx
+            .select_values::<T>(&[("COUNT(*)".to_string(), DataType::Int64)], &count_clauses)?
+            .pop()
+            .and_then(|mut row| row.pop().unwrap())
+            .map_or(0, |v| i64::try_from(v).unwrap() as u64);
+        let mut clauses = clauses;
+        clauses.order_by_rowid(T::schema());
+        clauses.limit = Some(self.page_size);
+        clauses.offset = page.saturating_mul(self.page_size);
+        Ok(Page {
+            items: tx.select_where(&clauses)?,
+            page,
+            page_size: self.page_size,
+            total,
+        })
+    }
+
+    /// Returns the page following the one `cursor` was returned with, or the
+    /// first page if `cursor` is `None`. Cursors encode the ordering values of
+    /// the last object of a page, so pages stay stable when objects are
+    /// inserted or deleted before them.
+    pub fn after(self, cursor: Option<&str>) -> Result<KeysetPage<'t, T>> {
+        if self.page_size == 0 {
+            return Err(Error::InvalidPageSize);
+        }
+        let Query {
+            tx, mut clauses, ..
+        } = self.query;
+        clauses.order_by_rowid(T::schema());
+        clauses.limit = Some(self.page_size + 1);
+        clauses.offset = 0;
+        if let Some(cursor) = cursor {
+            let values = decode_values(cursor)
+                .filter(|values| values.len() == clauses.order_by.len())
+                .ok_or(Error::InvalidCursor)?;
+            clauses.after(&values);
+        }
+        tx.begin_query::<T>()?;
+        let mut rows = tx.select_rows::<T>(&clauses)?;
+        let next_cursor = if rows.len() as u64 > self.page_size {
+            rows.truncate(self.page_size as usize);
+            let (rowid, row) = rows.last().unwrap();
+            Some(encode_values(&clauses.ordering_values(
+                T::schema(),
+                *rowid,
+                row,
+            )))
+        } else {
+            None
+        };
+        let mut items = Vec::with_capacity(rows.len());
+        for (rowid, row) in rows {
+            items.extend(tx.resolve(rowid, row, clauses.include_deleted())?);
+        }
+        Ok(KeysetPage { items, next_cursor })
+    }
+}
+
+/// A query grouped by a column of type `K`, returned by `Query::group_by`.
+pub struct GroupBy<'t, 'a, T: Object, K> {
+    query: Query<'t, 'a, T>,
+    column: Column<T, K>,
+}
+
+impl<'t, 'a, T: Object, K: ColumnValue> GroupBy<'t, 'a, T, K> {
+    pub fn count(self) -> Result<Vec<(K, u64)>> {
+        let column = self.column.name;
+        self.aggregate("COUNT(*)".to_string(), DataType::Int64, column, |v| {
+            Ok(i64::try_from(v).unwrap() as u64)
+        })
+    }
+
+    pub fn sum<V: Summable>(self, column: Column<T, V>) -> Result<Vec<(K, V::Sum)>> {
+        let expression = format!("COALESCE(SUM({}), 0)", column.name);
+        self.aggregate(expression, V::Sum::DATA_TYPE, column.name, |v| {
+            decode(column, v)
+        })
+    }
+
+    pub fn min<V: ColumnValue>(self, column: Column<T, V>) -> Result<Vec<(K, V)>> {
+        let expression = format!("MIN({})", column.name);
+        self.aggregate(expression, V::DATA_TYPE, column.name, |v| decode(column, v))
+    }
+
+    pub fn max<V: ColumnValue>(self, column: Column<T, V>) -> Result<Vec<(K, V)>> {
+        let expression = format!("MAX({})", column.name);
+        self.aggregate(expression, V::DATA_TYPE, column.name, |v| decode(column, v))
+    }
+
+    pub fn avg<V>(self, column: Column<T, V>) -> Result<Vec<(K, f64)>> {
+        let expression = format!("AVG({})", column.name);
+        self.aggregate(expression, DataType::Float64, column.name, |v| {
+            Ok(f64::try_from(v).unwrap())
+        })
+    }
+
+    /// Computes `expression` over the values of `column` in each group. A
+    /// `NULL` group key, or a group whose values are all `NULL`, is reported
+    /// like a `NULL` field of an object.
+    fn aggregate<V>(
+        self,
+        expression: String,
+        data_type: DataType,
+        column: &'static str,
+        decode_value: impl Fn(Value<'static>) -> Result<V>,
+    ) -> Result<Vec<(K, V)>> {
+        let schema = T::schema();
+        let null_error = |c| null_value_error(schema, field_index(schema, c));
+        let expressions = [
+            (self.column.name.to_string(), K::DATA_TYPE),
+            (expression, data_type),
+        ];
+        let rows = self
+            .query
+            .tx
+            .select_values::<T>(&expressions, &self.query.clauses)?;
+        rows.into_iter()
+            .map(|row| {
+                let mut values = row.into_iter();
+                let key = values
+                    .next()
+                    .unwrap()
+                    .ok_or_else(|| null_error(self.column.name))?;
+                let value = values.next().unwrap().ok_or_else(|| null_error(column))?;
+                Ok((decode(self.column, key)?, decode_value(value)?))
+            })
+            .collect()
+    }
+}
+
+/// How a `Join` pairs objects: `Inner` skips objects without a match, `Left`
+/// pairs them with `None`.
+pub trait JoinKind {
+    type Joined<'t, U: Object>;
+
+    #[doc(hidden)]
+    const LEFT: bool;
+    #[doc(hidden)]
+    fn joined<U: Object>(tx: Option<Tx<'_, U>>) -> Option<Self::Joined<'_, U>>;
+}
+
+/// An object paired with the object it refers to by a `Join` of kind `K`.
+pub type JoinPair<'t, T, U, K> = (Tx<'t, T>, <K as JoinKind>::Joined<'t, U>);
+
+pub struct Inner;
+
+impl JoinKind for Inner {
+    type Joined<'t, U: Object> = Tx<'t, U>;
+
+    const LEFT: bool = false;
+
+    fn joined<U: Object>(tx: Option<Tx<'_, U>>) -> Option<Tx<'_, U>> {
+        tx
+    }
+}
+
+pub struct Left;
+
+impl JoinKind for Left {
+    type Joined<'t, U: Object> = Option<Tx<'t, U>>;
+
+    const LEFT: bool = true;
+
+    fn joined<U: Object>(tx: Option<Tx<'_, U>>) -> Option<Option<Tx<'_, U>>> {
+        Some(tx)
+    }
+}
+
+/// A query over the objects of `T` paired with the objects of `U` they refer
+/// to, returned by `Query::join` and `Query::left_join`.
+pub struct Join<'t, 'a, T: Object, U: Object, K: JoinKind> {
+    query: Query<'t, 'a, T>,
+    column: &'static str,
+    marker: PhantomData<fn() -> (U, K)>,
+}
+
+impl<'t, 'a, T: Object, U: Object, K: JoinKind> Join<'t, 'a, T, U, K> {
+    fn new(query: Query<'t, 'a, T>, column: &'static str) -> Self {
+        Self {
+            query,
+            column,
+            marker: PhantomData,
+        }
+    }
+
+    /// Restricts the query to objects matching `filter`, in addition to any
+    /// filter given before.
+    pub fn filter(mut self, filter: Filter<T>) -> Self {
+        self.query.clauses.filter(filter);
+        self
+    }
+
+    /// Restricts the query to objects whose joined object matches `filter`.
+    pub fn filter_joined(mut self, filter: Filter<U>) -> Self {
+        self.query.clauses.filter(filter);
+        self
+    }
+
+    pub fn order_by<V>(mut self, column: Column<T, V>) -> Self {
+        self.query.clauses.order_by(column, false);
+        self
+    }
+
+    pub fn order_by_desc<V>(mut self, column: Column<T, V>) -> Self {
+        self.query.clauses.order_by(column, true);
+        self
+    }
+
+    pub fn order_by_joined<V>(mut self, column: Column<U, V>) -> Self {
+        self.query.clauses.order_by(column, false);
+        self
+    }
+
+    pub fn order_by_joined_desc<V>(mut self, column: Column<U, V>) -> Self {
+        self.query.clauses.order_by(column, true);
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.query.clauses.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.query.clauses.offset = offset;
+        self
+    }
+
+    /// Includes soft-deleted objects on both sides of the join.
+    pub fn with_deleted(mut self) -> Self {
+        self.query.clauses.include_deleted = true;
+        self
+    }
+
+    /// Loads the matching pairs, both sides through the identity map. Fails
+    /// with `InvalidJoin` if the column does not reference `U`, or if `U` is
+    /// `T`.
+    pub fn fetch(self) -> Result<Vec<JoinPair<'t, T, U, K>>> {
+        let (schema, joined) = (T::schema(), U::schema());
+        let references = schema.foreign_keys.iter().any(|f| {
+            f.column_name == self.column && (f.references)().table_name == joined.table_name
+        });
+        if !references || schema.table_name == joined.table_name {
+            return Err(invalid_join_error(schema, self.column, joined));
+        }
+        let pairs =
+            self.query
+                .tx
+                .select_joined::<T, U>(self.column, K::LEFT, &self.query.clauses)?;
+        Ok(pairs
+            .into_iter()
+            .filter_map(|(tx, other)| Some((tx, K::joined(other)?)))
+            .collect())
+    }
+}
+
+/// Columns of `T` selected by `Query::select`: a `Column`, or a tuple of up to
+/// six projections decoded into the tuple of their outputs.
+pub trait Projection<T> {
+    type Output;
+
+    fn push_columns(&self, columns: &mut Vec<(String, DataType)>);
+    fn decode(
+        &self,
+        values: &mut std::vec::IntoIter<Option<Value<'static>>>,
+    ) -> Result<Self::Output>;
+}
+
+impl<T: Object, V: ColumnValue> Projection<T> for Column<T, V> {
+    type Output = V;
+
+    fn push_columns(&self, columns: &mut Vec<(String, DataType)>) {
+        columns.push((self.name.to_string(), V::DATA_TYPE));
+    }
+
+    fn decode(&self, values: &mut std::vec::IntoIter<Option<Value<'static>>>) -> Result<V> {
+        let schema = T::schema();
+        match values.next().unwrap() {
+            Some(value) => decode(*self, value),
+            None => Err(null_error(schema, field_index(schema, self.name))),
+        }
+    }
+}
+
+macro_rules! impl_tuple_projection {
+    ($($name:ident),*) => {
+        impl<T, $($name: Projection<T>),*> Projection<T> for ($($name,)*) {
+            type Output = ($($name::Output,)*);
+
+            #[allow(non_snake_case)]
+            fn push_columns(&self, columns: &mut Vec<(String, DataType)>) {
+                let ($($name,)*) = self;
+                $($name.push_columns(columns);)*
+            }
+
+            #[allow(non_snake_case)]
+            fn decode(
+                &self,
+                values: &mut std::vec::IntoIter<Option<Value<'static>>>,
+            ) -> Result<Self::Output> {
+                let ($($name,)*) = self;
+                Ok(($($name.decode(values)?,)*))
+            }
+        }
+    };
+}
+
+impl_tuple_projection!(A);
+impl_tuple_projection!(A, B);
+impl_tuple_projection!(A, B, C);
+impl_tuple_projection!(A, B, C, D);
+impl_tuple_projection!(A, B, C, D, E);
+impl_tuple_projection!(A, B, C, D, E, F);
+
+fn null_error(schema: &Schema, column_index: usize) -> Error {
+    map_value_error(ValueError::UnexpectedType("Null"), schema, column_index)
+}
+
+/// Decodes `value`, computed from `column`, into `W`: the column type or the
+/// type of an aggregate over it.
+fn decode<T: Object, V, W: ColumnValue>(column: Column<T, V>, value: Value) -> Result<W> {
+    let schema = T::schema();
+    W::try_from(value).map_err(|e| map_value_error(e, schema, field_index(schema, column.name)))
+}
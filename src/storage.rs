@@ -1,19 +1,218 @@
 use crate::{
-    data::{DataType, Value},
-    error::{map_rusqlite_error, map_rusqlite_error_with_id, Result},
-    object::Schema,
+    data::{narrow_float, DataType, Timestamp, Value, ValueError},
+    error::{
+        map_join_error, map_rusqlite_error, map_rusqlite_error_with_key, map_value_error,
+        missing_column_error, out_of_range_error, Result,
+    },
+    history::{HistoryHead, HistoryRecord, Operation, HISTORY_TABLE},
+    object::{Collection, Element, Schema},
+    query::Clauses,
     ObjectId,
 };
 
-use rusqlite::ToSql;
+use rusqlite::{
+    types::{FromSql, FromSqlError, FromSqlResult, ValueRef},
+    ToSql,
+};
+
+use std::sync::OnceLock;
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// The column marking the rows of `#[orm(soft_delete)]` types as deleted.
+pub const DELETED_AT: &str = "deleted_at";
+
 pub type Row<'a> = Vec<Value<'a>>;
 pub type RowSlice<'a> = [Value<'a>];
 
+/// The rowid and values of a row matching a full-text search, with a snippet
+/// of the matching text and the rank of the match. Snippets enclose matched
+/// terms in square brackets.
+pub(crate) type SearchRow = (ObjectId, Row<'static>, String, f64);
+
+/// The rowid and values of a row, with those of the row joined to it if any.
+pub(crate) type JoinedRow = ((ObjectId, Row<'static>), Option<(ObjectId, Row<'static>)>);
+
+/// Decodes the values of a row one field at a time, reporting failures
+/// against the corresponding field of the schema.
+pub struct RowReader<'a, 's> {
+    schema: &'s Schema,
+    values: std::vec::IntoIter<Value<'a>>,
+    index: usize,
+}
+
+impl<'a, 's> RowReader<'a, 's> {
+    pub fn new(schema: &'s Schema, row: Row<'a>) -> Self {
+        Self {
+            schema,
+            values: row.into_iter(),
+            index: 0,
+        }
+    }
+
+    pub fn read<T>(&mut self) -> Result<T>
+    where
+        T: TryFrom<Value<'a>, Error = ValueError>,
+    {
+        let index = self.index;
+        let value = self
+            .values
+            .next()
+            .ok_or_else(|| missing_column_error(self.schema, index))?;
+        self.index += 1;
+        T::try_from(value).map_err(|e| map_value_error(e, self.schema, index))
+    }
+}
+
+/// Decodes the collection fields of an object in declaration order. A missing
+/// list of rows decodes as an empty collection.
+pub struct CollectionReader<'a, 's> {
+    collections: std::slice::Iter<'s, Collection>,
+    rows: std::vec::IntoIter<Vec<Row<'a>>>,
+}
+
+impl<'a, 's> CollectionReader<'a, 's> {
+    pub fn new(schema: &'s Schema, rows: Vec<Vec<Row<'a>>>) -> Self {
+        Self {
+            collections: schema.collections.iter(),
+            rows: rows.into_iter(),
+        }
+    }
+
+    pub fn read<E: Element>(&mut self) -> Result<Vec<E>> {
+        let collection = self
+            .collections
+            .next()
+            .expect("more collection fields than in the schema");
+        self.rows
+            .next()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|row| E::read(&mut RowReader::new(&collection.schema, row)))
+            .collect()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// SQL of the statements issued against a table, generated on first use. For
+/// collection tables the statements select the items by their owner's key.
+#[doc(hidden)]
+#[derive(Default)]
+pub struct Statements {
+    insert: OnceLock<String>,
+    update: OnceLock<String>,
+    select: OnceLock<String>,
+    delete: OnceLock<String>,
+    select_by: OnceLock<Vec<String>>,
+    select_with_deleted: OnceLock<String>,
+    soft_delete: OnceLock<String>,
+    restore: OnceLock<String>,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
+fn select_list(schema: &Schema) -> String {
+    if schema.fields.is_empty() {
+        "1".to_string()
+    } else {
+        list_fields(schema)
+    }
+}
+
+fn read_values(row: &rusqlite::Row, schema: &Schema) -> rusqlite::Result<Row<'static>> {
+    read_typed_values(row, schema.fields.iter().map(|f| f.data_type))
+}
+
+/// Reads the fields of `schema` from the columns starting at `offset`. Errors
+/// refer to the fields by their index in `schema`.
+fn read_values_at(
+    row: &rusqlite::Row,
+    schema: &Schema,
+    offset: usize,
+) -> rusqlite::Result<Row<'static>> {
+    schema
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| read_value(row, offset + i, f.data_type))
+        .collect::<rusqlite::Result<_>>()
+        .map_err(|e| map_column_index(e, |i| i - offset))
+}
+
+/// Renumbers the column a decoding error refers to, e.g. from a result column
+/// to a field of the schema.
+fn map_column_index(err: rusqlite::Error, f: impl FnOnce(usize) -> usize) -> rusqlite::Error {
+    match err {
+        rusqlite::Error::InvalidColumnType(i, name, got_type) => {
+            rusqlite::Error::InvalidColumnType(f(i), name, got_type)
+        }
+        rusqlite::Error::IntegralValueOutOfRange(i, value) => {
+            rusqlite::Error::IntegralValueOutOfRange(f(i), value)
+        }
+        rusqlite::Error::FromSqlConversionFailure(i, got_type, err) => {
+            rusqlite::Error::FromSqlConversionFailure(f(i), got_type, err)
+        }
+        e => e,
+    }
+}
+
+/// The result column a decoding error refers to, if any.
+fn error_column_index(err: &rusqlite::Error) -> Option<usize> {
+    match err {
+        rusqlite::Error::InvalidColumnType(i, ..)
+        | rusqlite::Error::IntegralValueOutOfRange(i, _)
+        | rusqlite::Error::FromSqlConversionFailure(i, ..) => Some(*i),
+        _ => None,
+    }
+}
+
+/// The field of `schema` read by an expression of `select_values`: a column,
+/// or the column an aggregate is computed from.
+fn expression_field(schema: &Schema, expression: &str) -> Option<usize> {
+    let argument = expression.rsplit('(').next().unwrap();
+    let column = argument.split(')').next().unwrap();
+    schema.fields.iter().position(|f| f.column_name == column)
+}
+
+fn read_typed_values(
+    row: &rusqlite::Row,
+    data_types: impl Iterator<Item = DataType>,
+) -> rusqlite::Result<Row<'static>> {
+    data_types
+        .enumerate()
+        .map(|(i, data_type)| read_value(row, i, data_type))
+        .collect()
+}
+
+fn read_value(
+    row: &rusqlite::Row,
+    i: usize,
+    data_type: DataType,
+) -> rusqlite::Result<Value<'static>> {
+    Ok(match data_type {
+        DataType::String => Value::String(row.get::<_, String>(i)?.into()),
+        DataType::Bytes => Value::Bytes(row.get::<_, Vec<u8>>(i)?.into()),
+        DataType::Int8 => Value::Int64(row.get::<_, i8>(i)?.into()),
+        DataType::Int16 => Value::Int64(row.get::<_, i16>(i)?.into()),
+        DataType::Int32 => Value::Int64(row.get::<_, i32>(i)?.into()),
+        DataType::Int64 => Value::Int64(row.get::<_, i64>(i)?),
+        DataType::UInt8 => Value::Int64(row.get::<_, u8>(i)?.into()),
+        DataType::UInt16 => Value::Int64(row.get::<_, u16>(i)?.into()),
+        DataType::UInt32 => Value::Int64(row.get::<_, u32>(i)?.into()),
+        DataType::UInt64 => Value::UInt64(row.get::<_, u64>(i)?),
+        DataType::Float32 => {
+            let x = row.get::<_, f64>(i)?;
+            narrow_float(x).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(i, rusqlite::types::Type::Real, e.into())
+            })?;
+            Value::Float64(x)
+        }
+        DataType::Float64 => Value::Float64(row.get::<_, f64>(i)?),
+        DataType::Bool => Value::Bool(row.get::<_, bool>(i)?),
+    })
+}
+
 fn list_fields(schema: &Schema) -> String {
     schema
         .fields
@@ -29,20 +228,518 @@ fn row_to_parameters<'a>(row: &'a RowSlice) -> Vec<&'a dyn ToSql> {
             Value::String(x) => x as &dyn ToSql,
             Value::Bytes(x) => x as &dyn ToSql,
             Value::Int64(x) => x as &dyn ToSql,
+            Value::UInt64(x) => x as &dyn ToSql,
             Value::Float64(x) => x as &dyn ToSql,
             Value::Bool(x) => x as &dyn ToSql,
         })
         .collect::<Vec<_>>()
 }
 
+fn placeholders(first: usize, count: usize) -> String {
+    (first..first + count)
+        .map(|i| format!("?{}", i))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// `a = ?first AND b = ?first+1 ...` over the given columns.
+fn columns_condition(columns: &[String], first: usize) -> String {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, c)| format!("{} = ?{}", c, first + i))
+        .collect::<Vec<_>>()
+        .join(" AND ")
+}
+
+fn key_column_names(schema: &Schema) -> Vec<String> {
+    schema
+        .key_columns()
+        .into_iter()
+        .map(|(c, _)| c.to_string())
+        .collect()
+}
+
+/// Columns of a collection table referencing the key of its owner.
+fn parent_column_names(owner: &Schema) -> Vec<String> {
+    owner
+        .key_columns()
+        .into_iter()
+        .map(|(c, _)| format!("parent_{}", c))
+        .collect()
+}
+
+/// `condition` restricted to the rows of `schema` that are not soft-deleted.
+pub(crate) fn live_condition(schema: &Schema, condition: &str) -> String {
+    if schema.soft_delete {
+        format!(
+            "({}) AND {}.{} IS NULL",
+            condition, schema.table_name, DELETED_AT
+        )
+    } else {
+        condition.to_string()
+    }
+}
+
+/// Maximum number of tokens in a search snippet.
+const SNIPPET_TOKENS: usize = 12;
+
+fn fulltext_table_name(schema: &Schema) -> String {
+    format!("{}_fts", schema.table_name)
+}
+
+/// Statements creating the FTS5 table indexing the fulltext fields of
+/// `schema` and the triggers keeping it in sync with the table, following
+/// the external content pattern of the SQLite documentation.
+fn create_fulltext_sql(schema: &Schema) -> Vec<String> {
+    let fts = fulltext_table_name(schema);
+    let table = schema.table_name;
+    let columns = schema
+        .fulltext
+        .iter()
+        .map(|&i| schema.fields[i].column_name)
+        .collect::<Vec<_>>();
+    let values = |prefix: &str| {
+        columns
+            .iter()
+            .map(|c| format!("{}.{}", prefix, c))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let columns = columns.join(",");
+    let insert = format!(
+        "INSERT INTO {}(rowid,{}) VALUES (new.rowid,{});",
+        fts,
+        columns,
+        values("new")
+    );
+    let delete = format!(
+        "INSERT INTO {}({},rowid,{}) VALUES ('delete',old.rowid,{});",
+        fts,
+        fts,
+        columns,
+        values("old")
+    );
+    vec![
+        format!(
+            "CREATE VIRTUAL TABLE {} USING fts5({},content='{}',content_rowid='rowid')",
+            fts, columns, table
+        ),
+        format!(
+            "CREATE TRIGGER {}_insert AFTER INSERT ON {} BEGIN {} END",
+            fts, table, insert
+        ),
+        format!(
+            "CREATE TRIGGER {}_delete AFTER DELETE ON {} BEGIN {} END",
+            fts, table, delete
+        ),
+        format!(
+            "CREATE TRIGGER {}_update AFTER UPDATE OF {} ON {} BEGIN {} {} END",
+            fts, columns, table, delete, insert
+        ),
+    ]
+}
+
+/// Inserts the keys and rows of objects into the snapshot table of `schema`.
+fn insert_snapshot_rows(
+    conn: &rusqlite::Connection,
+    schema: &Schema,
+    rows: &[(Row, Row)],
+) -> Result<()> {
+    let implicit_key = schema.primary_key.is_empty();
+    let names = implicit_key
+        .then_some("id")
+        .into_iter()
+        .chain(schema.fields.iter().map(|f| f.column_name))
+        .collect::<Vec<_>>();
+    let mut stmt = conn.prepare(
+        format!(
+            "INSERT INTO temp.{}({}) VALUES({})",
+            schema.table_name,
+            names.join(","),
+            placeholders(1, names.len())
+        )
+        .as_str(),
+    )?;
+    for (key, row) in rows {
+        let key = if implicit_key { key.as_slice() } else { &[] };
+        let parameters = row_to_parameters(key)
+            .into_iter()
+            .chain(row_to_parameters(row))
+            .collect::<Vec<_>>();
+        stmt.execute(parameters.as_slice())
+            .map_err(|e| map_rusqlite_error(e, schema))?;
+    }
+    Ok(())
+}
+
+fn insert_sql(schema: &Schema) -> &str {
+    schema.statements.insert.get_or_init(|| {
+        if schema.fields.is_empty() {
+            format!("INSERT INTO {} DEFAULT VALUES", schema.table_name)
+        } else {
+            format!(
+                "INSERT INTO {}({}) VALUES({})",
+                schema.table_name,
+                list_fields(schema),
+                placeholders(1, schema.fields.len())
+            )
+        }
+    })
+}
+
+fn update_sql(schema: &Schema) -> &str {
+    schema.statements.update.get_or_init(|| {
+        let set_sql = schema
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| format!("{} = ?{}", f.column_name, i + 1))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "UPDATE {} SET {} WHERE {}",
+            schema.table_name,
+            set_sql,
+            columns_condition(&key_column_names(schema), schema.fields.len() + 1)
+        )
+    })
+}
+
+fn select_sql(schema: &Schema) -> &str {
+    schema.statements.select.get_or_init(|| {
+        format!(
+            "SELECT {} FROM {} WHERE {}",
+            select_list(schema),
+            schema.table_name,
+            live_condition(schema, &columns_condition(&key_column_names(schema), 1))
+        )
+    })
+}
+
+fn select_with_deleted_sql(schema: &Schema) -> &str {
+    schema.statements.select_with_deleted.get_or_init(|| {
+        format!(
+            "SELECT {} FROM {} WHERE {}",
+            select_list(schema),
+            schema.table_name,
+            columns_condition(&key_column_names(schema), 1)
+        )
+    })
+}
+
+/// The statement setting `deleted_at` of a row that is not deleted yet, or
+/// clearing it for a row that is, with `restore`.
+fn set_deleted_at_sql(schema: &Schema, restore: bool) -> &str {
+    let statement = if restore {
+        &schema.statements.restore
+    } else {
+        &schema.statements.soft_delete
+    };
+    statement.get_or_init(|| {
+        format!(
+            "UPDATE {} SET {} = ?1 WHERE {} AND {} IS {}NULL",
+            schema.table_name,
+            DELETED_AT,
+            columns_condition(&key_column_names(schema), 2),
+            DELETED_AT,
+            if restore { "NOT " } else { "" }
+        )
+    })
+}
+
+fn delete_sql(schema: &Schema) -> &str {
+    schema.statements.delete.get_or_init(|| {
+        format!(
+            "DELETE FROM {} WHERE {}",
+            schema.table_name,
+            columns_condition(&key_column_names(schema), 1)
+        )
+    })
+}
+
+/// The statement selecting the first live row whose `column_name` equals the
+/// parameter, with the rowid as its last column.
+fn select_by_sql<'s>(schema: &'s Schema, column_name: &str) -> &'s str {
+    let statements = schema.statements.select_by.get_or_init(|| {
+        // The rowid goes last so that field indices match `schema.fields`.
+        let columns = schema
+            .fields
+            .iter()
+            .map(|f| f.column_name)
+            .chain(["rowid"])
+            .collect::<Vec<_>>()
+            .join(",");
+        schema
+            .fields
+            .iter()
+            .map(|f| {
+                format!(
+                    "SELECT {} FROM {} WHERE {} ORDER BY rowid LIMIT 1",
+                    columns,
+                    schema.table_name,
+                    live_condition(schema, &format!("{} = ?1", f.column_name))
+                )
+            })
+            .collect()
+    });
+    let i = schema
+        .fields
+        .iter()
+        .position(|f| f.column_name == column_name)
+        .unwrap();
+    &statements[i]
+}
+
+fn insert_collection_sql<'s>(owner: &Schema, collection: &'s Collection) -> &'s str {
+    let schema = &collection.schema;
+    schema.statements.insert.get_or_init(|| {
+        let columns = parent_column_names(owner)
+            .into_iter()
+            .chain(["position".to_string()])
+            .chain(schema.fields.iter().map(|f| f.column_name.to_string()))
+            .collect::<Vec<_>>();
+        format!(
+            "INSERT INTO {}({}) VALUES({})",
+            schema.table_name,
+            columns.join(","),
+            placeholders(1, columns.len())
+        )
+    })
+}
+
+fn select_collection_sql<'s>(owner: &Schema, collection: &'s Collection) -> &'s str {
+    let schema = &collection.schema;
+    schema.statements.select.get_or_init(|| {
+        format!(
+            "SELECT {} FROM {} WHERE {} ORDER BY position",
+            select_list(schema),
+            schema.table_name,
+            columns_condition(&parent_column_names(owner), 1)
+        )
+    })
+}
+
+fn delete_collection_sql<'s>(owner: &Schema, collection: &'s Collection) -> &'s str {
+    let schema = &collection.schema;
+    schema.statements.delete.get_or_init(|| {
+        format!(
+            "DELETE FROM {} WHERE {}",
+            schema.table_name,
+            columns_condition(&parent_column_names(owner), 1)
+        )
+    })
+}
+
+fn check_row_range(schema: &Schema, row: &RowSlice) -> Result<()> {
+    check_values_range(schema, 0.., row)
+}
+
+/// Checks `values` of the fields at `indices` fit the signed storage integer.
+fn check_values_range(
+    schema: &Schema,
+    indices: impl IntoIterator<Item = usize>,
+    values: &RowSlice,
+) -> Result<()> {
+    for (i, v) in indices.into_iter().zip(values) {
+        if let Value::UInt64(x) = v {
+            if i64::try_from(*x).is_err() {
+                return Err(out_of_range_error(schema, i, x.to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The type values of `data_type` are stored as, so that aggregates over narrow
+/// columns decode without a range error from the storage.
+fn widened(data_type: DataType) -> DataType {
+    match data_type {
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32 => DataType::Int64,
+        DataType::Float32 => DataType::Float64,
+        t => t,
+    }
+}
+
+/// The key columns to return from a statement, decoded by `read_keys`.
+fn returning_keys(schema: &Schema) -> String {
+    key_column_names(schema).join(",")
+}
+
+fn read_keys(row: &rusqlite::Row, schema: &Schema) -> rusqlite::Result<Row<'static>> {
+    read_typed_values(row, schema.key_columns().into_iter().map(|(_, t)| t))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl FromSql for Operation {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Operation::parse(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+const HISTORY_COLUMNS: &str = "object_type,table_name,object_key,operation,\
+                               before_values,after_values,changed_at,actor,hash";
+
+fn read_history_record(row: &rusqlite::Row) -> rusqlite::Result<HistoryRecord> {
+    Ok(HistoryRecord {
+        seq: row.get::<_, i64>(9)? as u64,
+        object_type: row.get(0)?,
+        table_name: row.get(1)?,
+        object_key: row.get(2)?,
+        operation: row.get(3)?,
+        before: row.get(4)?,
+        after: row.get(5)?,
+        changed_at: Timestamp::from_micros(row.get(6)?),
+        actor: row.get(7)?,
+        hash: row.get(8)?,
+    })
+}
+
 pub(crate) trait StorageTransaction {
     fn table_exists(&self, table: &str) -> Result<bool>;
     fn create_table(&self, schema: &Schema) -> Result<()>;
 
     fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId>;
-    fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()>;
-    fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>>;
-    fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()>;
+    /// Inserts `rows` with a single prepared statement, returning their rowids.
+    fn insert_rows(&self, schema: &Schema, rows: &[Row]) -> Result<Vec<ObjectId>>;
+    fn update_row(&self, key: &RowSlice, schema: &Schema, row: &RowSlice) -> Result<()>;
+    fn select_row(&self, key: &RowSlice, schema: &Schema) -> Result<Row<'static>>;
+    /// Like `select_row`, but also reads soft-deleted rows.
+    fn select_row_with_deleted(&self, key: &RowSlice, schema: &Schema) -> Result<Row<'static>>;
+    fn select_row_by(
+        &self,
+        schema: &Schema,
+        column_name: &str,
+        value: &Value,
+    ) -> Result<Option<(ObjectId, Row<'static>)>>;
+    /// Returns whether a row was deleted.
+    fn delete_row(&self, key: &RowSlice, schema: &Schema) -> Result<bool>;
+    /// Marks a row of a soft-deletable table as deleted at `deleted_at`, or
+    /// restores a deleted one with `None`. Returns whether a row changed.
+    fn set_deleted_at(
+        &self,
+        key: &RowSlice,
+        schema: &Schema,
+        deleted_at: Option<Timestamp>,
+    ) -> Result<bool>;
+
+    /// Selects the rows matching `clauses`, each followed by its rowid.
+    fn select_rows(
+        &self,
+        schema: &Schema,
+        clauses: &Clauses,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>>;
+    /// Selects one value per expression for each row or group matching
+    /// `clauses`. `NULL` values are returned as `None`.
+    fn select_values(
+        &self,
+        schema: &Schema,
+        expressions: &[(String, DataType)],
+        clauses: &Clauses,
+    ) -> Result<Vec<Vec<Option<Value<'static>>>>>;
+
+    /// Selects the rows of `schema` matching `clauses` along with the row of
+    /// `joined` whose key equals their `column`, each followed by its rowid.
+    /// With `left`, rows without a match are kept and paired with `None`.
+    fn select_joined(
+        &self,
+        schema: &Schema,
+        joined: &Schema,
+        column: &str,
+        left: bool,
+        clauses: &Clauses,
+    ) -> Result<Vec<JoinedRow>>;
+
+    /// Selects the rows whose fulltext fields match the FTS5 `query`, best
+    /// matches first.
+    fn search(&self, schema: &Schema, query: &str) -> Result<Vec<SearchRow>>;
+
+    /// Runs `sql` and decodes each row into the fields of `schema`, matching
+    /// result columns by name. Rows come with the value of their `id` column
+    /// if there is one.
+    fn select_raw(
+        &self,
+        schema: &Schema,
+        sql: &str,
+        parameters: &RowSlice,
+    ) -> Result<Vec<(Option<ObjectId>, Row<'static>)>>;
+    fn execute_raw(&self, sql: &str, parameters: &RowSlice) -> Result<usize>;
+
+    /// Sets the fields at `indices` to `values` in the rows matching `filter`,
+    /// returning the keys of the updated rows.
+    fn update_where(
+        &self,
+        schema: &Schema,
+        indices: &[usize],
+        values: &RowSlice,
+        filter: &str,
+        parameters: &RowSlice,
+    ) -> Result<Vec<Row<'static>>>;
+    /// Marks the rows matching `filter` that are not deleted yet as deleted at
+    /// `deleted_at`, returning their keys.
+    fn soft_delete_where(
+        &self,
+        schema: &Schema,
+        filter: &str,
+        parameters: &RowSlice,
+        deleted_at: Timestamp,
+    ) -> Result<Vec<Row<'static>>>;
+    /// Deletes the rows matching `filter`, returning their keys.
+    fn delete_where(
+        &self,
+        schema: &Schema,
+        filter: &str,
+        parameters: &RowSlice,
+    ) -> Result<Vec<Row<'static>>>;
+
+    fn insert_collection(
+        &self,
+        key: &RowSlice,
+        owner: &Schema,
+        collection: &Collection,
+        rows: &[Row],
+    ) -> Result<()>;
+    fn select_collection(
+        &self,
+        key: &RowSlice,
+        owner: &Schema,
+        collection: &Collection,
+    ) -> Result<Vec<Row<'static>>>;
+    fn delete_collection(
+        &self,
+        key: &RowSlice,
+        owner: &Schema,
+        collection: &Collection,
+    ) -> Result<()>;
+
+    /// Creates the table recording the changes to `#[orm(audit)]` types.
+    fn create_history_table(&self) -> Result<()>;
+    /// Returns the hash of the last history record, if any.
+    fn history_head(&self) -> Result<Option<HistoryHead>>;
+    fn insert_history(&self, record: &HistoryRecord) -> Result<()>;
+    /// Selects the history records of the object of `table` with the encoded
+    /// `key`, or all records with `None`, in order.
+    fn select_history(&self, object: Option<(&str, &str)>) -> Result<Vec<HistoryRecord>>;
+    /// Selects the last history record of each object of `table` changed at
+    /// or before `at`, or of the object with the encoded `key` only.
+    fn select_history_as_of(
+        &self,
+        table: &str,
+        key: Option<&str>,
+        at: Timestamp,
+    ) -> Result<Vec<HistoryRecord>>;
+    /// Creates a temporary table shadowing the table of `schema` and holding
+    /// `rows`, each stored under its key. Queries see it instead of the table
+    /// until `drop_snapshot_table`; if the rows cannot be inserted, it is
+    /// dropped before returning the error.
+    fn create_snapshot_table(&self, schema: &Schema, rows: &[(Row, Row)]) -> Result<()>;
+    fn drop_snapshot_table(&self, schema: &Schema) -> Result<()>;
 
     fn commit(&self) -> Result<()>;
     fn rollback(&self) -> Result<()>;
@@ -51,108 +748,687 @@ pub(crate) trait StorageTransaction {
 impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
     fn table_exists(&self, table: &str) -> Result<bool> {
         let mut stmt =
-            self.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?;
+            self.prepare_cached("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?;
         Ok(stmt.exists([table])?)
     }
 
     fn create_table(&self, schema: &Schema) -> Result<()> {
-        let fields = ["id INTEGER PRIMARY KEY AUTOINCREMENT".to_string()]
-            .into_iter()
-            .chain(schema.fields.iter().map(|f| f.get_create_sql()))
-            .collect::<Vec<_>>()
-            .join(",");
+        let key = if schema.primary_key.is_empty() {
+            "id INTEGER PRIMARY KEY AUTOINCREMENT".to_string()
+        } else {
+            format!("PRIMARY KEY({})", key_column_names(schema).join(","))
+        };
+        let fields = schema
+            .fields
+            .iter()
+            .map(|f| f.get_create_sql())
+            .chain(schema.soft_delete.then(|| format!("{} BIGINT", DELETED_AT)))
+            .chain(schema.checks.iter().map(|c| c.get_create_sql()))
+            .chain(schema.foreign_keys.iter().map(|f| f.get_create_sql()));
+        let fields = if schema.primary_key.is_empty() {
+            [key].into_iter().chain(fields).collect::<Vec<_>>()
+        } else {
+            fields.chain([key]).collect::<Vec<_>>()
+        };
         self.execute(
-            format!("CREATE TABLE {}({})", schema.table_name, fields).as_str(),
+            format!("CREATE TABLE {}({})", schema.table_name, fields.join(",")).as_str(),
             [],
         )?;
+        for index in &schema.indexes {
+            self.execute(index.get_create_sql(schema.table_name).as_str(), [])?;
+        }
+        if !schema.fulltext.is_empty() {
+            for sql in create_fulltext_sql(schema) {
+                self.execute(sql.as_str(), [])?;
+            }
+        }
+        let parent_columns = parent_column_names(schema);
+        for collection in &schema.collections {
+            let fields = parent_columns
+                .iter()
+                .zip(schema.key_columns())
+                .map(|(c, (_, data_type))| format!("{} {} NOT NULL", c, data_type.sql_type()))
+                .chain(["position INTEGER NOT NULL".to_string()])
+                .chain(collection.schema.fields.iter().map(|f| f.get_create_sql()))
+                .chain([
+                    format!(
+                        "FOREIGN KEY({}) REFERENCES {}({}) ON DELETE CASCADE",
+                        parent_columns.join(","),
+                        schema.table_name,
+                        key_column_names(schema).join(",")
+                    ),
+                    format!("PRIMARY KEY({},position)", parent_columns.join(",")),
+                ])
+                .collect::<Vec<_>>()
+                .join(",");
+            self.execute(
+                format!("CREATE TABLE {}({})", collection.schema.table_name, fields).as_str(),
+                [],
+            )?;
+        }
         Ok(())
     }
 
     fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId> {
-        let placeholders = (1..=row.len())
-            .map(|i| format!("?{}", i))
-            .collect::<Vec<_>>()
-            .join(",");
-        let sql = if schema.fields.is_empty() {
-            format!("INSERT INTO {} DEFAULT VALUES", schema.table_name)
-        } else {
-            format!(
-                "INSERT INTO {}({}) VALUES({})",
-                schema.table_name,
-                list_fields(schema),
-                placeholders
-            )
-        };
-        self.execute(sql.as_str(), row_to_parameters(row).as_slice())
+        check_row_range(schema, row)?;
+        let mut stmt = self.prepare_cached(insert_sql(schema))?;
+        let rowid = stmt
+            .insert(row_to_parameters(row).as_slice())
             .map_err(|e| map_rusqlite_error(e, schema))?;
-        Ok(self.last_insert_rowid().into())
+        Ok(rowid.into())
+    }
+
+    fn insert_rows(&self, schema: &Schema, rows: &[Row]) -> Result<Vec<ObjectId>> {
+        let mut stmt = self.prepare_cached(insert_sql(schema))?;
+        rows.iter()
+            .map(|row| {
+                check_row_range(schema, row)?;
+                stmt.insert(row_to_parameters(row).as_slice())
+                    .map(ObjectId::from)
+                    .map_err(|e| map_rusqlite_error(e, schema))
+            })
+            .collect()
     }
 
-    fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
+    fn update_row(&self, key: &RowSlice, schema: &Schema, row: &RowSlice) -> Result<()> {
         if schema.fields.is_empty() {
             return Ok(());
         }
+        check_row_range(schema, row)?;
         let mut parameters = row_to_parameters(row);
-        let id_parameter = &id.into_i64();
-        parameters.push(id_parameter);
-        let set_sql = (1..=row.len())
-            .zip(schema.fields)
-            .map(|(i, f)| format!("{} = ?{}", f.column_name, i))
-            .chain(["id = id".to_string()])
+        parameters.extend(row_to_parameters(key));
+        self.prepare_cached(update_sql(schema))?
+            .execute(parameters.as_slice())
+            .map_err(|e| map_rusqlite_error(e, schema))?;
+        Ok(())
+    }
+
+    fn select_row(&self, key: &RowSlice, schema: &Schema) -> Result<Row<'static>> {
+        let map_err = |e| map_rusqlite_error_with_key(e, schema, key);
+        let mut stmt = self.prepare_cached(select_sql(schema)).map_err(map_err)?;
+        stmt.query_row(row_to_parameters(key).as_slice(), |row| {
+            read_values(row, schema)
+        })
+        .map_err(map_err)
+    }
+
+    fn select_row_with_deleted(&self, key: &RowSlice, schema: &Schema) -> Result<Row<'static>> {
+        let map_err = |e| map_rusqlite_error_with_key(e, schema, key);
+        let mut stmt = self
+            .prepare_cached(select_with_deleted_sql(schema))
+            .map_err(map_err)?;
+        stmt.query_row(row_to_parameters(key).as_slice(), |row| {
+            read_values(row, schema)
+        })
+        .map_err(map_err)
+    }
+
+    fn select_row_by(
+        &self,
+        schema: &Schema,
+        column_name: &str,
+        value: &Value,
+    ) -> Result<Option<(ObjectId, Row<'static>)>> {
+        let map_err = |e| map_rusqlite_error(e, schema);
+        let mut stmt = self
+            .prepare_cached(select_by_sql(schema, column_name))
+            .map_err(map_err)?;
+        let mut rows = stmt
+            .query(row_to_parameters(std::slice::from_ref(value)).as_slice())
+            .map_err(map_err)?;
+        match rows.next().map_err(map_err)? {
+            Some(row) => {
+                let rowid = row.get::<_, i64>(schema.fields.len()).map_err(map_err)?;
+                Ok(Some((
+                    rowid.into(),
+                    read_values(row, schema).map_err(map_err)?,
+                )))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn delete_row(&self, key: &RowSlice, schema: &Schema) -> Result<bool> {
+        let count = self
+            .prepare_cached(delete_sql(schema))
+            .and_then(|mut stmt| stmt.execute(row_to_parameters(key).as_slice()))
+            .map_err(|e| map_rusqlite_error(e, schema))?;
+        Ok(count > 0)
+    }
+
+    fn set_deleted_at(
+        &self,
+        key: &RowSlice,
+        schema: &Schema,
+        deleted_at: Option<Timestamp>,
+    ) -> Result<bool> {
+        let sql = set_deleted_at_sql(schema, deleted_at.is_none());
+        let deleted_at = deleted_at.map(|t| t.as_micros());
+        let parameters = [&deleted_at as &dyn ToSql]
+            .into_iter()
+            .chain(row_to_parameters(key))
+            .collect::<Vec<_>>();
+        let count = self
+            .prepare_cached(sql)
+            .and_then(|mut stmt| stmt.execute(parameters.as_slice()))
+            .map_err(|e| map_rusqlite_error(e, schema))?;
+        Ok(count > 0)
+    }
+
+    fn select_rows(
+        &self,
+        schema: &Schema,
+        clauses: &Clauses,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let map_err = |e| map_rusqlite_error(e, schema);
+        let columns = schema
+            .fields
+            .iter()
+            .map(|f| f.column_name)
+            .chain(["rowid"])
             .collect::<Vec<_>>()
             .join(",");
-        self.execute(
-            format!(
-                "UPDATE {} SET {} WHERE id = ?{}",
-                schema.table_name,
-                set_sql,
-                parameters.len()
+        let mut stmt = self
+            .prepare(
+                format!(
+                    "SELECT {} FROM {}{}",
+                    columns,
+                    schema.table_name,
+                    clauses.to_sql(schema)
+                )
+                .as_str(),
             )
-            .as_str(),
-            parameters.as_slice(),
-        )?;
-        Ok(())
+            .map_err(map_err)?;
+        let rows = stmt
+            .query_map(row_to_parameters(clauses.parameters()).as_slice(), |row| {
+                let rowid = row.get::<_, i64>(schema.fields.len())?;
+                Ok((rowid.into(), read_values(row, schema)?))
+            })
+            .map_err(map_err)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(map_err)
     }
 
-    fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>> {
-        let map_err = |e| map_rusqlite_error_with_id(e, schema, id);
+    fn select_values(
+        &self,
+        schema: &Schema,
+        expressions: &[(String, DataType)],
+        clauses: &Clauses,
+    ) -> Result<Vec<Vec<Option<Value<'static>>>>> {
+        let map_err = |e| map_rusqlite_error(e, schema);
+        let select_list = expressions
+            .iter()
+            .map(|(e, _)| e.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
         let mut stmt = self
             .prepare(
                 format!(
-                    "SELECT {} FROM {} WHERE id = ?1",
-                    if schema.fields.is_empty() {
-                        "1".to_string()
-                    } else {
-                        list_fields(schema)
-                    },
-                    schema.table_name
+                    "SELECT {} FROM {}{}",
+                    select_list,
+                    schema.table_name,
+                    clauses.to_sql(schema)
                 )
                 .as_str(),
             )
             .map_err(map_err)?;
-        stmt.query_row([id.into_i64()], |row| {
-            schema
-                .fields
-                .iter()
-                .enumerate()
-                .map(|(i, f)| {
-                    Ok(match f.data_type {
-                        DataType::String => Value::String(row.get::<_, String>(i)?.into()),
-                        DataType::Bytes => Value::Bytes(row.get::<_, Vec<u8>>(i)?.into()),
-                        DataType::Int64 => Value::Int64(row.get::<_, i64>(i)?),
-                        DataType::Float64 => Value::Float64(row.get::<_, f64>(i)?),
-                        DataType::Bool => Value::Bool(row.get::<_, bool>(i)?),
+        let rows = stmt
+            .query_map(row_to_parameters(clauses.parameters()).as_slice(), |row| {
+                expressions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, data_type))| {
+                        if row.get_ref(i)? == rusqlite::types::ValueRef::Null {
+                            return Ok(None);
+                        }
+                        read_value(row, i, widened(*data_type)).map(Some)
                     })
-                })
-                .collect()
+                    .collect()
+            })
+            .map_err(map_err)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(|e| {
+            let field =
+                error_column_index(&e).and_then(|i| expression_field(schema, &expressions[i].0));
+            match field {
+                Some(field) => map_rusqlite_error(map_column_index(e, |_| field), schema),
+                None => e.into(),
+            }
         })
-        .map_err(map_err)
     }
 
-    fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()> {
+    fn select_joined(
+        &self,
+        schema: &Schema,
+        joined: &Schema,
+        column: &str,
+        left: bool,
+        clauses: &Clauses,
+    ) -> Result<Vec<JoinedRow>> {
+        let map_err = |e| map_join_error(e, schema, joined);
+        let columns = [schema, joined]
+            .iter()
+            .flat_map(|s| {
+                s.fields
+                    .iter()
+                    .map(|f| f.column_name)
+                    .chain(["rowid"])
+                    .map(|c| format!("{}.{}", s.table_name, c))
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let joined_key = match joined.key_columns().as_slice() {
+            [(key, _)] => *key,
+            _ => panic!("cannot join '{}' by a composite key", joined.type_name),
+        };
+        let mut on = format!(
+            "{}.{} = {}.{}",
+            schema.table_name, column, joined.table_name, joined_key
+        );
+        if !clauses.include_deleted() {
+            on = live_condition(joined, &on);
+        }
+        let mut stmt = self
+            .prepare(
+                format!(
+                    "SELECT {} FROM {} {} JOIN {} ON {}{}",
+                    columns,
+                    schema.table_name,
+                    if left { "LEFT" } else { "INNER" },
+                    joined.table_name,
+                    on,
+                    clauses.to_sql(schema)
+                )
+                .as_str(),
+            )
+            .map_err(map_err)?;
+        let offset = schema.fields.len() + 1;
+        let mut rows = stmt
+            .query(row_to_parameters(clauses.parameters()).as_slice())
+            .map_err(map_err)?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next().map_err(map_err)? {
+            let map_err = |e| map_rusqlite_error(e, schema);
+            let rowid = row.get::<_, i64>(offset - 1).map_err(map_err)?;
+            let values = read_values(row, schema).map_err(map_err)?;
+            let map_err = |e| map_rusqlite_error(e, joined);
+            let other = match row
+                .get::<_, Option<i64>>(offset + joined.fields.len())
+                .map_err(map_err)?
+            {
+                Some(rowid) => Some((
+                    rowid.into(),
+                    read_values_at(row, joined, offset).map_err(map_err)?,
+                )),
+                None => None,
+            };
+            result.push(((rowid.into(), values), other));
+        }
+        Ok(result)
+    }
+
+    fn search(&self, schema: &Schema, query: &str) -> Result<Vec<SearchRow>> {
+        let map_err = |e| map_rusqlite_error(e, schema);
+        let fts = fulltext_table_name(schema);
+        let columns = schema
+            .fields
+            .iter()
+            .map(|f| f.column_name)
+            .chain(["rowid"])
+            .map(|c| format!("{}.{}", schema.table_name, c))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut stmt = self
+            .prepare_cached(
+                format!(
+                    "SELECT {}, snippet({}, -1, '[', ']', '...', {}), {}.rank \
+                    FROM {} JOIN {} ON {}.rowid = {}.rowid \
+                    WHERE {} ORDER BY {}.rank",
+                    columns,
+                    fts,
+                    SNIPPET_TOKENS,
+                    fts,
+                    fts,
+                    schema.table_name,
+                    schema.table_name,
+                    fts,
+                    live_condition(schema, &format!("{} MATCH ?", fts)),
+                    fts
+                )
+                .as_str(),
+            )
+            .map_err(map_err)?;
+        let offset = schema.fields.len();
+        let rows = stmt
+            .query_map([query], |row| {
+                Ok((
+                    row.get::<_, i64>(offset)?.into(),
+                    read_values(row, schema)?,
+                    row.get(offset + 1)?,
+                    row.get(offset + 2)?,
+                ))
+            })
+            .map_err(map_err)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(map_err)
+    }
+
+    fn select_raw(
+        &self,
+        schema: &Schema,
+        sql: &str,
+        parameters: &RowSlice,
+    ) -> Result<Vec<(Option<ObjectId>, Row<'static>)>> {
+        let map_err = |e| map_rusqlite_error(e, schema);
+        let mut stmt = self.prepare(sql)?;
+        let names = stmt.column_names();
+        let indices = schema
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                names
+                    .iter()
+                    .position(|n| *n == f.column_name)
+                    .ok_or_else(|| missing_column_error(schema, i))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let id_index = names.iter().position(|n| *n == "id");
+        let rows = stmt
+            .query_map(row_to_parameters(parameters).as_slice(), |row| {
+                let id = id_index
+                    .map(|i| row.get::<_, i64>(i).map(ObjectId::from))
+                    .transpose()?;
+                // Decoding errors refer to result columns; report them for
+                // the field read from the column.
+                let values = indices
+                    .iter()
+                    .zip(&schema.fields)
+                    .enumerate()
+                    .map(|(field, (&i, f))| {
+                        read_value(row, i, f.data_type).map_err(|e| map_column_index(e, |_| field))
+                    })
+                    .collect::<rusqlite::Result<_>>()
+                    .map_err(map_err);
+                Ok(values.map(|values| (id, values)))
+            })
+            .map_err(map_err)?;
+        rows.map(|row| row?).collect()
+    }
+
+    fn execute_raw(&self, sql: &str, parameters: &RowSlice) -> Result<usize> {
+        Ok(self.execute(sql, row_to_parameters(parameters).as_slice())?)
+    }
+
+    fn update_where(
+        &self,
+        schema: &Schema,
+        indices: &[usize],
+        values: &RowSlice,
+        filter: &str,
+        parameters: &RowSlice,
+    ) -> Result<Vec<Row<'static>>> {
+        check_values_range(schema, indices.iter().copied(), values)?;
+        let map_err = |e| map_rusqlite_error(e, schema);
+        let set_sql = indices
+            .iter()
+            .map(|&i| format!("{} = ?", schema.fields[i].column_name))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut stmt = self
+            .prepare(
+                format!(
+                    "UPDATE {} SET {} WHERE {} RETURNING {}",
+                    schema.table_name,
+                    set_sql,
+                    live_condition(schema, filter),
+                    returning_keys(schema)
+                )
+                .as_str(),
+            )
+            .map_err(map_err)?;
+        let mut all_parameters = row_to_parameters(values);
+        all_parameters.extend(row_to_parameters(parameters));
+        let rows = stmt
+            .query_map(all_parameters.as_slice(), |row| read_keys(row, schema))
+            .map_err(map_err)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(map_err)
+    }
+
+    fn soft_delete_where(
+        &self,
+        schema: &Schema,
+        filter: &str,
+        parameters: &RowSlice,
+        deleted_at: Timestamp,
+    ) -> Result<Vec<Row<'static>>> {
+        let map_err = |e| map_rusqlite_error(e, schema);
+        let mut stmt = self
+            .prepare(
+                format!(
+                    "UPDATE {} SET {} = ? WHERE {} RETURNING {}",
+                    schema.table_name,
+                    DELETED_AT,
+                    live_condition(schema, filter),
+                    returning_keys(schema)
+                )
+                .as_str(),
+            )
+            .map_err(map_err)?;
+        let deleted_at = deleted_at.as_micros();
+        let all_parameters = [&deleted_at as &dyn ToSql]
+            .into_iter()
+            .chain(row_to_parameters(parameters))
+            .collect::<Vec<_>>();
+        let rows = stmt
+            .query_map(all_parameters.as_slice(), |row| read_keys(row, schema))
+            .map_err(map_err)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(map_err)
+    }
+
+    fn delete_where(
+        &self,
+        schema: &Schema,
+        filter: &str,
+        parameters: &RowSlice,
+    ) -> Result<Vec<Row<'static>>> {
+        let map_err = |e| map_rusqlite_error(e, schema);
+        let mut stmt = self
+            .prepare(
+                format!(
+                    "DELETE FROM {} WHERE {} RETURNING {}",
+                    schema.table_name,
+                    filter,
+                    returning_keys(schema)
+                )
+                .as_str(),
+            )
+            .map_err(map_err)?;
+        let rows = stmt
+            .query_map(row_to_parameters(parameters).as_slice(), |row| {
+                read_keys(row, schema)
+            })
+            .map_err(map_err)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(map_err)
+    }
+
+    fn insert_collection(
+        &self,
+        key: &RowSlice,
+        owner: &Schema,
+        collection: &Collection,
+        rows: &[Row],
+    ) -> Result<()> {
+        let schema = &collection.schema;
+        let mut stmt = self.prepare_cached(insert_collection_sql(owner, collection))?;
+        for (position, row) in rows.iter().enumerate() {
+            check_row_range(schema, row)?;
+            let position_parameter = &(position as i64);
+            let parameters = row_to_parameters(key)
+                .into_iter()
+                .chain([position_parameter as &dyn ToSql])
+                .chain(row_to_parameters(row))
+                .collect::<Vec<_>>();
+            stmt.execute(parameters.as_slice())
+                .map_err(|e| map_rusqlite_error(e, schema))?;
+        }
+        Ok(())
+    }
+
+    fn select_collection(
+        &self,
+        key: &RowSlice,
+        owner: &Schema,
+        collection: &Collection,
+    ) -> Result<Vec<Row<'static>>> {
+        let schema = &collection.schema;
+        let map_err = |e| map_rusqlite_error(e, schema);
+        let mut stmt = self
+            .prepare_cached(select_collection_sql(owner, collection))
+            .map_err(map_err)?;
+        let rows = stmt
+            .query_map(row_to_parameters(key).as_slice(), |row| {
+                read_values(row, schema)
+            })
+            .map_err(map_err)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(map_err)
+    }
+
+    fn delete_collection(
+        &self,
+        key: &RowSlice,
+        owner: &Schema,
+        collection: &Collection,
+    ) -> Result<()> {
+        self.prepare_cached(delete_collection_sql(owner, collection))
+            .and_then(|mut stmt| stmt.execute(row_to_parameters(key).as_slice()))
+            .map_err(|e| map_rusqlite_error(e, &collection.schema))?;
+        Ok(())
+    }
+
+    fn create_history_table(&self) -> Result<()> {
         self.execute(
-            format!("DELETE FROM {} WHERE id = ?1", schema.table_name).as_str(),
-            [id.into_i64()],
+            format!(
+                "CREATE TABLE {}(seq INTEGER PRIMARY KEY AUTOINCREMENT,\
+                 object_type TEXT NOT NULL,table_name TEXT NOT NULL,\
+                 object_key TEXT NOT NULL,operation TEXT NOT NULL,before_values TEXT,\
+                 after_values TEXT,changed_at BIGINT NOT NULL,actor TEXT,hash TEXT NOT NULL)",
+                HISTORY_TABLE
+            )
+            .as_str(),
+            [],
+        )?;
+        self.execute(
+            format!(
+                "CREATE INDEX {0}_object ON {0}(table_name,object_key)",
+                HISTORY_TABLE
+            )
+            .as_str(),
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn history_head(&self) -> Result<Option<HistoryHead>> {
+        let mut stmt = self.prepare_cached(&format!(
+            "SELECT seq, hash FROM {} ORDER BY seq DESC LIMIT 1",
+            HISTORY_TABLE
+        ))?;
+        let mut rows = stmt.query([])?;
+        Ok(match rows.next()? {
+            Some(row) => Some(HistoryHead {
+                seq: row.get(0)?,
+                hash: row.get(1)?,
+            }),
+            None => None,
+        })
+    }
+
+    fn insert_history(&self, record: &HistoryRecord) -> Result<()> {
+        self.prepare_cached(&format!(
+            "INSERT INTO {}({}) VALUES({})",
+            HISTORY_TABLE,
+            HISTORY_COLUMNS,
+            placeholders(1, 9)
+        ))?
+        .execute(rusqlite::params![
+            record.object_type,
+            record.table_name,
+            record.object_key,
+            record.operation.as_str(),
+            record.before,
+            record.after,
+            record.changed_at.as_micros(),
+            record.actor,
+            record.hash,
+        ])?;
+        Ok(())
+    }
+
+    fn select_history(&self, object: Option<(&str, &str)>) -> Result<Vec<HistoryRecord>> {
+        let mut sql = format!("SELECT {},seq FROM {}", HISTORY_COLUMNS, HISTORY_TABLE);
+        let mut parameters: Vec<&str> = Vec::new();
+        if let Some((table, key)) = &object {
+            sql.push_str(" WHERE table_name = ?1 AND object_key = ?2");
+            parameters.extend([*table, *key]);
+        }
+        sql.push_str(" ORDER BY seq");
+        let mut stmt = self.prepare_cached(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(parameters), read_history_record)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn select_history_as_of(
+        &self,
+        table: &str,
+        key: Option<&str>,
+        at: Timestamp,
+    ) -> Result<Vec<HistoryRecord>> {
+        let sql = format!(
+            "SELECT {0},seq FROM {1} WHERE seq IN (SELECT MAX(seq) FROM {1} \
+             WHERE table_name = ?1 AND changed_at <= ?2{2} GROUP BY object_key) ORDER BY seq",
+            HISTORY_COLUMNS,
+            HISTORY_TABLE,
+            if key.is_some() {
+                " AND object_key = ?3"
+            } else {
+                ""
+            }
+        );
+        let mut stmt = self.prepare_cached(&sql)?;
+        let at = at.as_micros();
+        let mut parameters = vec![&table as &dyn ToSql, &at];
+        parameters.extend(key.as_ref().map(|key| key as &dyn ToSql));
+        let rows = stmt.query_map(parameters.as_slice(), read_history_record)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn create_snapshot_table(&self, schema: &Schema, rows: &[(Row, Row)]) -> Result<()> {
+        let implicit_key = schema.primary_key.is_empty();
+        let columns = implicit_key
+            .then(|| "id INTEGER PRIMARY KEY".to_string())
+            .into_iter()
+            .chain(schema.fields.iter().map(|f| f.get_create_sql()))
+            .chain(schema.soft_delete.then(|| format!("{} BIGINT", DELETED_AT)))
+            .collect::<Vec<_>>();
+        self.execute(
+            format!(
+                "CREATE TEMP TABLE {}({})",
+                schema.table_name,
+                columns.join(",")
+            )
+            .as_str(),
+            [],
+        )?;
+        // The temporary table hides the real one until it is dropped.
+        let inserted = insert_snapshot_rows(self, schema, rows);
+        if inserted.is_err() {
+            self.drop_snapshot_table(schema)?;
+        }
+        inserted
+    }
+
+    fn drop_snapshot_table(&self, schema: &Schema) -> Result<()> {
+        self.execute(
+            format!("DROP TABLE temp.{}", schema.table_name).as_str(),
+            [],
         )?;
         Ok(())
     }
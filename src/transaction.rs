@@ -1,14 +1,20 @@
 use crate::{
-    data::ObjectId,
+    clock::Clock,
+    data::{decode_values, encode_values, DataType, ObjectId, Timestamp, Value},
     error::*,
-    object::{Object, Store},
-    storage::StorageTransaction,
+    history::{
+        decode_snapshot, encode_snapshot, object_snapshot, HistoryEntry, HistoryHead,
+        HistoryRecord, Operation, HISTORY_TABLE,
+    },
+    object::{Audited, FromRow, Object, PrimaryKey, Schema, Searchable, SoftDelete, Store},
+    query::{Clauses, Column, Delete, Filter, JoinPair, Left, Query, SearchHit, Update, ValueOf},
+    storage::{Row, RowSlice, StorageTransaction, DELETED_AT},
 };
 
 use std::{
-    any::Any,
+    any::TypeId,
     cell::{Ref, RefCell, RefMut},
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap, HashSet},
     marker::PhantomData,
     rc::Rc,
 };
@@ -17,13 +23,35 @@ use std::{
 
 type Repr = Rc<RefCell<CacheValue<dyn Store>>>;
 
+/// Objects are identified by their type and the values of their key columns.
+type CacheKey = (TypeId, Row<'static>);
+
 pub struct Transaction<'a> {
     inner: Box<dyn StorageTransaction + 'a>,
-    cache: RefCell<HashMap<ObjectId, Repr>>,
+    clock: Rc<dyn Clock>,
+    cache: RefCell<HashMap<CacheKey, Repr>>,
+    /// Objects deleted by key with `delete_by_id` without being loaded.
+    tombstones: RefCell<HashMap<CacheKey, &'static Schema>>,
+    actor: RefCell<Option<String>>,
+    /// Changes to `#[orm(audit)]` types, written to the history on commit.
+    changes: RefCell<Vec<Change>>,
+}
+
+/// A change to an object of an `#[orm(audit)]` type, with its encoded key and
+/// snapshots.
+struct Change {
+    schema: &'static Schema,
+    key: String,
+    operation: Operation,
+    before: Option<String>,
+    after: Option<String>,
 }
 
 struct CacheValue<T: ?Sized> {
     state: ObjectState,
+    /// Whether the object was loaded by a query including soft-deleted rows
+    /// and may be one, which `get` has to check.
+    maybe_deleted: bool,
     obj: T,
 }
 
@@ -31,74 +59,982 @@ impl<T> CacheValue<T> {
     fn new(obj: T) -> Self {
         CacheValue {
             state: ObjectState::Clean,
+            maybe_deleted: false,
             obj,
         }
     }
 }
 
+fn filter_parts<T>(filter: Option<&Filter<T>>) -> (&str, &RowSlice<'static>) {
+    match filter {
+        Some(filter) => (filter.sql(), filter.parameters()),
+        None => ("1", &[]),
+    }
+}
+
+/// The values of the key columns of the object stored in `row` under `rowid`.
+fn key_values(schema: &Schema, rowid: ObjectId, row: &RowSlice) -> Row<'static> {
+    if schema.primary_key.is_empty() {
+        vec![Value::from(&rowid).into_owned()]
+    } else {
+        schema
+            .primary_key
+            .iter()
+            .map(|&i| row[i].clone().into_owned())
+            .collect()
+    }
+}
+
+/// Whether the key fields of `obj` no longer hold `key`, the key it is cached
+/// under. Objects keyed by an implicit `id` cannot change it.
+fn key_changed(obj: &dyn Store, key: &RowSlice) -> bool {
+    let row = obj.to_row();
+    let primary_key = &obj.get_schema().primary_key;
+    primary_key.iter().zip(key).any(|(&i, k)| row[i] != *k)
+}
+
+/// Borrows a cached object to write its pending change, or returns `None` if
+/// it has none. Objects still borrowed through their handle cannot be written.
+fn pending(v: &Repr) -> Result<Option<RefMut<'_, CacheValue<dyn Store>>>> {
+    if matches!(v.try_borrow().map(|x| x.state), Ok(ObjectState::Clean)) {
+        return Ok(None);
+    }
+    match v.try_borrow_mut() {
+        Ok(value) => Ok(Some(value)),
+        Err(_) => Err(Error::ObjectBorrowed),
+    }
+}
+
+fn cache_key<T: Object>(key: &T::Key) -> CacheKey {
+    (
+        TypeId::of::<T>(),
+        key.to_values().into_iter().map(Value::into_owned).collect(),
+    )
+}
+
 impl<'a> Transaction<'a> {
-    pub(crate) fn new(inner: Box<dyn StorageTransaction + 'a>) -> Self {
+    pub(crate) fn new(inner: Box<dyn StorageTransaction + 'a>, clock: Rc<dyn Clock>) -> Self {
         Self {
             inner,
+            clock,
             cache: RefCell::default(),
+            tombstones: RefCell::default(),
+            actor: RefCell::default(),
+            changes: RefCell::default(),
         }
     }
 
+    /// Sets the actor recorded with the changes this transaction makes to
+    /// `#[orm(audit)]` types, e.g. the name of the signed-in user.
+    pub fn set_actor(&self, actor: impl Into<String>) {
+        *self.actor.borrow_mut() = Some(actor.into());
+    }
+
     fn ensure_table_exists<T: Object>(&self) -> Result<()> {
-        if !self.inner.table_exists(T::SCHEMA.table_name)? {
-            self.inner.create_table(&T::SCHEMA)?;
+        self.ensure_schema_table_exists(T::schema())
+    }
+
+    /// Creates the table of `schema` if needed, along with the tables it
+    /// references, which have to exist for its rows to be checked.
+    fn ensure_schema_table_exists(&self, schema: &'static Schema) -> Result<()> {
+        if !self.inner.table_exists(schema.table_name)? {
+            self.inner.create_table(schema)?;
+            for foreign_key in &schema.foreign_keys {
+                self.ensure_schema_table_exists((foreign_key.references)())?;
+            }
         }
         Ok(())
     }
 
-    pub fn create<T: Object>(&self, obj: T) -> Result<Tx<'_, T>> {
+    pub fn create<T: Object>(&self, mut obj: T) -> Result<Tx<'_, T>> {
+        obj.before_insert()?;
+        obj.set_timestamps(self.clock.now(), true);
+        self.ensure_table_exists::<T>()?;
+        let schema = T::schema();
+        let rowid = self.inner.insert_row(schema, &obj.to_row())?;
+        let key = obj.key(rowid);
+        let cache_key = cache_key::<T>(&key);
+        for (collection, rows) in schema.collections.iter().zip(obj.collections_to_rows()) {
+            self.inner
+                .insert_collection(&cache_key.1, schema, collection, &rows)?;
+        }
+        if schema.audit {
+            let after = object_snapshot(&obj);
+            self.record(schema, &cache_key.1, Operation::Insert, None, Some(after));
+        }
+        let rc = Rc::new(RefCell::new(CacheValue::new(obj))) as Repr;
+        self.cache.borrow_mut().insert(cache_key, rc.clone());
+        Ok(Tx::new(key, rc))
+    }
+
+    /// Creates all objects of `objs`, inserting their rows with one prepared
+    /// statement. Much faster than calling `create` in a loop for large imports.
+    /// On error, rows inserted before the failing one stay in the storage
+    /// without handles; roll the transaction back to discard them.
+    pub fn create_many<T: Object>(
+        &self,
+        objs: impl IntoIterator<Item = T>,
+    ) -> Result<Vec<Tx<'_, T>>> {
         self.ensure_table_exists::<T>()?;
-        let id = self.inner.insert_row(&T::SCHEMA, &obj.to_row())?;
-        let rc = Rc::new(RefCell::new(CacheValue::new(obj))) as Rc<RefCell<CacheValue<dyn Store>>>;
-        self.cache.borrow_mut().insert(id, rc.clone());
-        Ok(Tx::new(id, rc))
+        let schema = T::schema();
+        let mut objs = objs.into_iter().collect::<Vec<_>>();
+        let now = self.clock.now();
+        for obj in &mut objs {
+            obj.before_insert()?;
+            obj.set_timestamps(now, true);
+        }
+        let rowids = {
+            let rows = objs.iter().map(|obj| obj.to_row()).collect::<Vec<_>>();
+            self.inner.insert_rows(schema, &rows)?
+        };
+        let mut txs = Vec::with_capacity(objs.len());
+        for (obj, rowid) in objs.into_iter().zip(rowids) {
+            let key = obj.key(rowid);
+            let cache_key = cache_key::<T>(&key);
+            for (collection, rows) in schema.collections.iter().zip(obj.collections_to_rows()) {
+                self.inner
+                    .insert_collection(&cache_key.1, schema, collection, &rows)?;
+            }
+            if schema.audit {
+                let after = object_snapshot(&obj);
+                self.record(schema, &cache_key.1, Operation::Insert, None, Some(after));
+            }
+            let rc = Rc::new(RefCell::new(CacheValue::new(obj))) as Repr;
+            self.cache.borrow_mut().insert(cache_key, rc.clone());
+            txs.push(Tx::new(key, rc));
+        }
+        Ok(txs)
     }
 
-    pub fn get<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
+    pub fn get<T: Object>(&self, key: T::Key) -> Result<Tx<'_, T>> {
+        let cache_key = cache_key::<T>(&key);
+        if self.tombstones.borrow().contains_key(&cache_key) {
+            return Err(not_found_error(T::schema(), &cache_key.1));
+        }
         let mut cache = self.cache.borrow_mut();
-        let rc = match cache.entry(id) {
-            std::collections::hash_map::Entry::Occupied(x) => {
+        let rc = match cache.entry(cache_key) {
+            Entry::Occupied(x) => {
                 let e = x.get();
-                match e.borrow().state {
-                    ObjectState::Removed => {
-                        return Err(Error::NotFound(Box::new(NotFoundError {
-                            object_id: id,
-                            type_name: T::SCHEMA.type_name,
-                        })))
+                let (state, maybe_deleted) = {
+                    let value = e.borrow();
+                    (value.state, value.maybe_deleted)
+                };
+                if state == ObjectState::Removed {
+                    return Err(not_found_error(T::schema(), &x.key().1));
+                }
+                if maybe_deleted {
+                    self.inner.select_row(&x.key().1, T::schema())?;
+                    if let Ok(mut value) = e.try_borrow_mut() {
+                        value.maybe_deleted = false;
                     }
-                    _ => e.clone(),
                 }
+                e.clone()
             }
-            std::collections::hash_map::Entry::Vacant(x) => {
+            Entry::Vacant(x) => {
                 self.ensure_table_exists::<T>()?;
-                let row = self.inner.select_row(id, &T::SCHEMA)?;
-                let rc = Rc::new(RefCell::new(CacheValue::new(T::from_row(row))))
-                    as Rc<RefCell<CacheValue<dyn Store>>>;
+                let row = self.inner.select_row(&x.key().1, T::schema())?;
+                let rc = self.load::<T>(&x.key().1, row)?;
                 x.insert(rc.clone());
                 rc
             }
         };
-        Ok(Tx::new(id, rc))
+        Ok(Tx::new(key, rc))
     }
 
-    pub fn commit(self) -> Result<()> {
-        for (id, v) in self.cache.borrow().iter() {
-            let value = &v.borrow();
-            let obj = &value.obj;
+    /// Returns the first object (by rowid) whose `column` equals `value`, like
+    /// `get` but keyed by a field, typically a unique one.
+    pub fn get_by<T: Object, V>(
+        &self,
+        column: Column<T, V>,
+        value: &(impl ValueOf<V> + ?Sized),
+    ) -> Result<Tx<'_, T>> {
+        let value = value.to_value();
+        self.find_by(column, &value)?
+            .ok_or_else(|| not_found_by_error(T::schema(), column.name(), &value))
+    }
+
+    /// Like `get_by`, but returns `None` if there is no such object.
+    pub fn find_one<T: Object, V>(
+        &self,
+        column: Column<T, V>,
+        value: &(impl ValueOf<V> + ?Sized),
+    ) -> Result<Option<Tx<'_, T>>> {
+        self.find_by(column, &value.to_value())
+    }
+
+    // Lookups run against the storage after flushing pending changes: the row
+    // found there is resolved through the cache, so objects removed in this
+    // transaction are not returned.
+    fn find_by<T: Object, V>(
+        &self,
+        column: Column<T, V>,
+        value: &Value,
+    ) -> Result<Option<Tx<'_, T>>> {
+        self.begin_query::<T>()?;
+        let schema = T::schema();
+        match self.inner.select_row_by(schema, column.name(), value)? {
+            Some((rowid, row)) => self.resolve(rowid, row, false),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the handle of the object stored in `row`, loading it into the
+    /// cache unless it is there already, or `None` if it is removed.
+    /// Returns the handle of the object stored in `row`, loading it into the
+    /// cache unless it is there already, or `None` if it was removed in this
+    /// transaction. `include_deleted` tells whether `row` may be soft-deleted.
+    pub(crate) fn resolve<T: Object>(
+        &self,
+        rowid: ObjectId,
+        row: Row<'static>,
+        include_deleted: bool,
+    ) -> Result<Option<Tx<'_, T>>> {
+        let cache_key = (TypeId::of::<T>(), key_values(T::schema(), rowid, &row));
+        if self.tombstones.borrow().contains_key(&cache_key) {
+            return Ok(None);
+        }
+        let mut cache = self.cache.borrow_mut();
+        let rc = match cache.get(&cache_key) {
+            Some(e) if e.borrow().state == ObjectState::Removed => return Ok(None),
+            Some(e) => e.clone(),
+            None => {
+                let rc = self.load::<T>(&cache_key.1, row)?;
+                rc.borrow_mut().maybe_deleted = include_deleted && T::schema().soft_delete;
+                cache.insert(cache_key, rc.clone());
+                rc
+            }
+        };
+        let key = rc
+            .borrow()
+            .obj
+            .as_any()
+            .downcast_ref::<T>()
+            .unwrap()
+            .key(rowid);
+        Ok(Some(Tx::new(key, rc)))
+    }
+
+    fn load<T: Object>(&self, key: &[Value], row: Row) -> Result<Repr> {
+        Ok(Rc::new(RefCell::new(CacheValue::new(
+            self.read_object::<T>(key, row)?,
+        ))) as Repr)
+    }
+
+    /// Decodes the object stored in `row` without going through the cache.
+    pub(crate) fn read_detached<T: Object>(&self, rowid: ObjectId, row: Row) -> Result<T> {
+        let key = key_values(T::schema(), rowid, &row);
+        self.read_object(&key, row)
+    }
+
+    fn read_object<T: Object>(&self, key: &[Value], row: Row) -> Result<T> {
+        let schema = T::schema();
+        let collections = schema
+            .collections
+            .iter()
+            .map(|c| self.inner.select_collection(key, schema, c))
+            .collect::<Result<_>>()?;
+        let mut obj = T::from_row(row, collections)?;
+        obj.after_load()?;
+        Ok(obj)
+    }
+
+    /// Deletes the object with the given key without loading it. The row is
+    /// deleted on the next flush, which fails with `NotFound` if there is no
+    /// such row after applying the other pending deletes. Queries, aggregates,
+    /// searches, bulk updates and deletes and raw statements flush first, so
+    /// they can return that error as well as `commit`. Deleting an object
+    /// that is already removed in this transaction returns `NotFound` right
+    /// away, and deleting a cached object still borrowed through its handle
+    /// returns `ObjectBorrowed`.
+    pub fn delete_by_id<T: Object>(&self, key: T::Key) -> Result<()> {
+        self.ensure_table_exists::<T>()?;
+        let cache_key = cache_key::<T>(&key);
+        let schema = T::schema();
+        if let Some(rc) = self.cache.borrow().get(&cache_key) {
+            let mut value = rc.try_borrow_mut().map_err(|_| Error::ObjectBorrowed)?;
+            if value.state == ObjectState::Removed {
+                return Err(not_found_error(schema, &cache_key.1));
+            }
+            value.state = ObjectState::Removed;
+            return Ok(());
+        }
+        match self.tombstones.borrow_mut().entry(cache_key) {
+            Entry::Occupied(x) => Err(not_found_error(schema, &x.key().1)),
+            Entry::Vacant(x) => {
+                x.insert(schema);
+                Ok(())
+            }
+        }
+    }
+
+    /// Starts a query over the objects of `T`, e.g.
+    /// `tx.query::<T>().filter(...).order_by(column).fetch()`.
+    pub fn query<T: Object>(&self) -> Query<'_, 'a, T> {
+        Query::new(self)
+    }
+
+    /// Prepares a query over `T`: the table must exist and the pending
+    /// changes must be written for the query to see them.
+    pub(crate) fn begin_query<T: Object>(&self) -> Result<()> {
+        self.ensure_table_exists::<T>()?;
+        self.flush()
+    }
+
+    pub(crate) fn select_rows<T: Object>(
+        &self,
+        clauses: &Clauses,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        self.inner.select_rows(T::schema(), clauses)
+    }
+
+    pub(crate) fn select_where<T: Object>(&self, clauses: &Clauses) -> Result<Vec<Tx<'_, T>>> {
+        self.begin_query::<T>()?;
+        let rows = self.select_rows::<T>(clauses)?;
+        let mut txs = Vec::with_capacity(rows.len());
+        for (rowid, row) in rows {
+            txs.extend(self.resolve(rowid, row, clauses.include_deleted())?);
+        }
+        Ok(txs)
+    }
+
+    /// Selects the objects of `T` matching `clauses` with the `U` referenced
+    /// by their `column`, or `None` for unmatched objects of a left join.
+    /// Both sides are loaded through the identity map.
+    pub(crate) fn select_joined<T: Object, U: Object>(
+        &self,
+        column: &str,
+        left: bool,
+        clauses: &Clauses,
+    ) -> Result<Vec<JoinPair<'_, T, U, Left>>> {
+        self.ensure_table_exists::<U>()?;
+        self.begin_query::<T>()?;
+        let rows = self
+            .inner
+            .select_joined(T::schema(), U::schema(), column, left, clauses)?;
+        let mut pairs = Vec::with_capacity(rows.len());
+        for ((rowid, row), other) in rows {
+            let tx = match self.resolve::<T>(rowid, row, clauses.include_deleted())? {
+                Some(tx) => tx,
+                None => continue,
+            };
+            let other = match other {
+                Some((rowid, row)) => self.resolve::<U>(rowid, row, clauses.include_deleted())?,
+                None => None,
+            };
+            pairs.push((tx, other));
+        }
+        Ok(pairs)
+    }
+
+    pub(crate) fn select_values<T: Object>(
+        &self,
+        expressions: &[(String, DataType)],
+        clauses: &Clauses,
+    ) -> Result<Vec<Vec<Option<Value<'static>>>>> {
+        self.begin_query::<T>()?;
+        self.inner.select_values(T::schema(), expressions, clauses)
+    }
+
+    /// Searches the `#[orm(fulltext)]` fields of `T` with an FTS5 query, e.g.
+    /// `"printer AND (jam OR stuck)"`, best matches first. Pending changes are
+    /// flushed first.
+    pub fn search<T: Searchable>(&self, query: &str) -> Result<Vec<SearchHit<'_, T>>> {
+        let schema = T::schema();
+        self.begin_query::<T>()?;
+        let rows = self.inner.search(schema, query)?;
+        let mut hits = Vec::with_capacity(rows.len());
+        for (rowid, row, snippet, rank) in rows {
+            if let Some(item) = self.resolve(rowid, row, false)? {
+                hits.push(SearchHit {
+                    item,
+                    snippet,
+                    rank,
+                });
+            }
+        }
+        Ok(hits)
+    }
+
+    /// Runs arbitrary SQL and decodes the rows into objects of `T`, matching
+    /// result columns to fields by name. Objects are resolved through the
+    /// identity map, so the result must include the key columns: `id` for
+    /// types without `#[orm(primary_key)]` fields. Pending changes are
+    /// flushed first.
+    pub fn query_raw<T: Object>(&self, sql: &str, params: &[Value]) -> Result<Vec<Tx<'_, T>>> {
+        self.begin_query::<T>()?;
+        let schema = T::schema();
+        let rows = self.inner.select_raw(schema, sql, params)?;
+        let mut txs = Vec::with_capacity(rows.len());
+        for (id, row) in rows {
+            // Only tables keyed by an implicit `id` need it to resolve the key.
+            let rowid = match id {
+                Some(id) => id,
+                None if schema.primary_key.is_empty() => {
+                    return Err(missing_key_column_error(schema))
+                }
+                None => ObjectId::from(0),
+            };
+            // Raw SQL may select soft-deleted rows.
+            txs.extend(self.resolve(rowid, row, true)?);
+        }
+        Ok(txs)
+    }
+
+    /// Like `query_raw`, but decodes objects without adding them to the
+    /// identity map, so the key columns are optional. Collections are only
+    /// loaded when they are.
+    pub fn query_raw_detached<T: Object>(&self, sql: &str, params: &[Value]) -> Result<Vec<T>> {
+        self.begin_query::<T>()?;
+        let schema = T::schema();
+        self.inner
+            .select_raw(schema, sql, params)?
+            .into_iter()
+            .map(|(id, row)| match id {
+                Some(id) => self.read_detached(id, row),
+                None if schema.primary_key.is_empty() => T::from_row(row, Vec::new()),
+                None => self.read_detached(ObjectId::from(0), row),
+            })
+            .collect()
+    }
+
+    /// Runs arbitrary SQL, e.g. a report joining several tables, and decodes
+    /// the rows into `R`, matching result columns to fields by name. Pending
+    /// changes are flushed first.
+    pub fn query_as<R: FromRow>(&self, sql: &str, params: &[Value]) -> Result<Vec<R>> {
+        self.flush()?;
+        self.inner
+            .select_raw(R::schema(), sql, params)?
+            .into_iter()
+            .map(|(_, row)| R::from_row(row))
+            .collect()
+    }
+
+    /// Runs an arbitrary SQL statement and returns the number of affected
+    /// rows. Pending changes are flushed first; objects already loaded in
+    /// this transaction are not refreshed.
+    pub fn execute_raw(&self, sql: &str, params: &[Value]) -> Result<usize> {
+        self.flush()?;
+        self.inner.execute_raw(sql, params)
+    }
+
+    /// Starts a set-based update of the objects of `T`, run without loading
+    /// them, e.g. `tx.update::<T>().filter(...).set(column, value).execute()`.
+    pub fn update<T: Object>(&self) -> Update<'_, 'a, T> {
+        Update::new(self)
+    }
+
+    /// Starts a set-based delete of the objects of `T`, run without loading
+    /// them, e.g. `tx.delete::<T>().filter(...).execute()`.
+    pub fn delete<T: Object>(&self) -> Delete<'_, 'a, T> {
+        Delete::new(self)
+    }
+
+    pub(crate) fn update_where<T: Object>(
+        &self,
+        indices: &[usize],
+        values: &RowSlice,
+        filter: Option<&Filter<T>>,
+    ) -> Result<usize> {
+        self.ensure_table_exists::<T>()?;
+        self.flush()?;
+        let schema = T::schema();
+        let (sql, parameters) = filter_parts(filter);
+        self.check_unborrowed::<T>(sql, parameters, false)?;
+        let mut before = self.audited_rows::<T>(sql, parameters, false)?;
+        let keys = self
+            .inner
+            .update_where(schema, indices, values, sql, parameters)?;
+        if schema.audit {
+            for key in &keys {
+                let row = self.inner.select_row(key, schema)?;
+                let after = self.stored_snapshot(key, schema, &row)?;
+                let before = before.remove(key);
+                if before.as_ref() != Some(&after) {
+                    self.record(schema, key, Operation::Update, before, Some(after));
+                }
+            }
+        }
+        let cache = self.cache.borrow();
+        for key in &keys {
+            if let Some(rc) = cache.get(&(TypeId::of::<T>(), key.clone())) {
+                let row = self.inner.select_row(key, schema)?;
+                let obj = self.read_object::<T>(key, row)?;
+                *rc.borrow_mut()
+                    .obj
+                    .as_any_mut()
+                    .downcast_mut::<T>()
+                    .unwrap() = obj;
+            }
+        }
+        Ok(keys.len())
+    }
+
+    pub(crate) fn delete_where<T: Object>(&self, filter: Option<&Filter<T>>) -> Result<usize> {
+        self.ensure_table_exists::<T>()?;
+        self.flush()?;
+        let schema = T::schema();
+        let (sql, parameters) = filter_parts(filter);
+        if schema.soft_delete {
+            self.check_unborrowed::<T>(sql, parameters, false)?;
+            let mut before = self.audited_rows::<T>(sql, parameters, false)?;
+            let keys = self
+                .inner
+                .soft_delete_where(schema, sql, parameters, self.clock.now())?;
+            if schema.audit {
+                for key in &keys {
+                    self.record(schema, key, Operation::Delete, before.remove(key), None);
+                }
+            }
+            Ok(self.evict::<T>(keys))
+        } else {
+            self.purge_where::<T>(sql, parameters)
+        }
+    }
+
+    /// Deletes for good the objects of `T` soft-deleted before
+    /// `deleted_before`, e.g. once a retention period is over. Returns the
+    /// number of purged objects.
+    pub fn purge<T: SoftDelete>(&self, deleted_before: Timestamp) -> Result<usize> {
+        let schema = T::schema();
+        self.ensure_table_exists::<T>()?;
+        self.flush()?;
+        let filter = format!("{}.{} < ?", schema.table_name, DELETED_AT);
+        self.purge_where::<T>(&filter, &[Value::from(&deleted_before)])
+    }
+
+    /// Restores a soft-deleted object of `T`, failing with `NotFound` if
+    /// there is no such object marked as deleted.
+    pub fn restore<T: SoftDelete>(&self, key: T::Key) -> Result<Tx<'_, T>> {
+        let schema = T::schema();
+        self.ensure_table_exists::<T>()?;
+        self.flush()?;
+        let values = key.to_values();
+        if !self.inner.set_deleted_at(&values, schema, None)? {
+            return Err(not_found_error(schema, &values));
+        }
+        if schema.audit {
+            let row = self.inner.select_row(&values, schema)?;
+            let after = self.stored_snapshot(&values, schema, &row)?;
+            self.record(schema, &values, Operation::Restore, None, Some(after));
+        }
+        self.get(key)
+    }
+
+    /// Deletes the rows of `T` matching `filter` with their collections.
+    fn purge_where<T: Object>(&self, filter: &str, parameters: &RowSlice) -> Result<usize> {
+        let schema = T::schema();
+        self.check_unborrowed::<T>(filter, parameters, true)?;
+        let mut before = self.audited_rows::<T>(filter, parameters, true)?;
+        let keys = self.inner.delete_where(schema, filter, parameters)?;
+        for key in &keys {
+            if schema.audit {
+                // Soft-deletable rows are only deleted for good by `purge`.
+                let operation = if schema.soft_delete {
+                    Operation::Purge
+                } else {
+                    Operation::Delete
+                };
+                self.record(schema, key, operation, before.remove(key), None);
+            }
+            for collection in &schema.collections {
+                self.inner.delete_collection(key, schema, collection)?;
+            }
+        }
+        Ok(self.evict::<T>(keys))
+    }
+
+    /// Encodes the stored object of `schema` with `key` and `row` as a history
+    /// snapshot, reading its collections.
+    fn stored_snapshot(&self, key: &RowSlice, schema: &Schema, row: &RowSlice) -> Result<String> {
+        let collections = schema
+            .collections
+            .iter()
+            .map(|c| self.inner.select_collection(key, schema, c))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(encode_snapshot(row, &collections))
+    }
+
+    /// Snapshots the stored objects of `T` matching `filter` by key, before a
+    /// set-based statement changes them. Empty unless `T` is audited.
+    fn audited_rows<T: Object>(
+        &self,
+        filter: &str,
+        parameters: &RowSlice,
+        include_deleted: bool,
+    ) -> Result<HashMap<Row<'static>, String>> {
+        let schema = T::schema();
+        let mut snapshots = HashMap::new();
+        if !schema.audit {
+            return Ok(snapshots);
+        }
+        let clauses = Clauses::matching(filter, parameters, include_deleted);
+        for (rowid, row) in self.inner.select_rows(schema, &clauses)? {
+            let key = key_values(schema, rowid, &row);
+            let snapshot = self.stored_snapshot(&key, schema, &row)?;
+            snapshots.insert(key, snapshot);
+        }
+        Ok(snapshots)
+    }
+
+    /// Fails with `ObjectBorrowed` if a cached object of `T` matching `filter`
+    /// is still borrowed through its handle, before a set-based statement
+    /// changes it.
+    fn check_unborrowed<T: Object>(
+        &self,
+        filter: &str,
+        parameters: &RowSlice,
+        include_deleted: bool,
+    ) -> Result<()> {
+        let schema = T::schema();
+        let cache = self.cache.borrow();
+        let borrowed = cache
+            .iter()
+            .filter(|((t, _), v)| *t == TypeId::of::<T>() && v.try_borrow_mut().is_err())
+            .map(|((_, key), _)| key)
+            .collect::<HashSet<_>>();
+        if borrowed.is_empty() {
+            return Ok(());
+        }
+        let clauses = Clauses::matching(filter, parameters, include_deleted);
+        for (rowid, row) in self.inner.select_rows(schema, &clauses)? {
+            if borrowed.contains(&key_values(schema, rowid, &row)) {
+                return Err(Error::ObjectBorrowed);
+            }
+        }
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        schema: &'static Schema,
+        key: &RowSlice,
+        operation: Operation,
+        before: Option<String>,
+        after: Option<String>,
+    ) {
+        self.changes.borrow_mut().push(Change {
+            schema,
+            key: encode_values(key),
+            operation,
+            before,
+            after,
+        });
+    }
+
+    /// Drops the cached objects of `T` with the given keys, marking their
+    /// handles as removed. Returns the number of keys. The objects must have
+    /// been checked with `check_unborrowed`.
+    fn evict<T: Object>(&self, keys: Vec<Row<'static>>) -> usize {
+        let mut cache = self.cache.borrow_mut();
+        let count = keys.len();
+        for key in keys {
+            if let Some(rc) = cache.remove(&(TypeId::of::<T>(), key)) {
+                rc.borrow_mut().state = ObjectState::Removed;
+            }
+        }
+        count
+    }
+
+    /// Writes the changes made to objects of this transaction to the storage
+    /// without committing them. Set-based statements flush first, so that
+    /// they see those changes.
+    pub fn flush(&self) -> Result<()> {
+        let mut cache = self.cache.borrow_mut();
+        // Hooks and checks run before any write, so that a failing one leaves
+        // the storage untouched.
+        for ((_, key), v) in cache.iter() {
+            let mut value = match pending(v)? {
+                Some(value) => value,
+                None => continue,
+            };
             match value.state {
                 ObjectState::Clean => {}
                 ObjectState::Modified => {
-                    self.inner
-                        .update_row(*id, obj.get_schema(), &obj.to_row())?
+                    value.obj.before_update()?;
+                    if key_changed(&value.obj, key) {
+                        return Err(key_changed_error(value.obj.get_schema(), key));
+                    }
                 }
-                ObjectState::Removed => self.inner.delete_row(*id, obj.get_schema())?,
+                ObjectState::Removed => value.obj.before_delete()?,
+            }
+        }
+        let now = self.clock.now();
+        for ((_, key), v) in cache.iter() {
+            let mut value = match pending(v)? {
+                Some(value) => value,
+                None => continue,
             };
+            let schema = value.obj.get_schema();
+            match value.state {
+                ObjectState::Clean => {}
+                ObjectState::Modified => {
+                    value.obj.set_timestamps(now, false);
+                    let obj = &value.obj;
+                    if schema.audit {
+                        // Objects loaded with `with_deleted` may be deleted.
+                        let row = self.inner.select_row_with_deleted(key, schema)?;
+                        let before = self.stored_snapshot(key, schema, &row)?;
+                        let after = object_snapshot(obj);
+                        if before != after {
+                            self.record(schema, key, Operation::Update, Some(before), Some(after));
+                        }
+                    }
+                    self.inner.update_row(key, schema, &obj.to_row())?;
+                    for (collection, rows) in
+                        schema.collections.iter().zip(obj.collections_to_rows())
+                    {
+                        self.inner.delete_collection(key, schema, collection)?;
+                        self.inner
+                            .insert_collection(key, schema, collection, &rows)?;
+                    }
+                    value.state = ObjectState::Clean;
+                }
+                ObjectState::Removed => {
+                    self.remove_row(key, schema, now)?;
+                }
+            };
+        }
+        cache.retain(|_, v| v.borrow().state != ObjectState::Removed);
+        // Every pending delete is applied before a missing row is reported.
+        let tombstones = std::mem::take(&mut *self.tombstones.borrow_mut());
+        let mut missing = None;
+        for ((_, key), schema) in tombstones {
+            if !self.remove_row(&key, schema, now)? && missing.is_none() {
+                missing = Some(not_found_error(schema, &key));
+            }
         }
+        match missing {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Deletes the row with `key` and its collections, or marks it as deleted
+    /// at `now` for `#[orm(soft_delete)]` types. Returns whether there was
+    /// such a row.
+    fn remove_row(&self, key: &RowSlice, schema: &'static Schema, now: Timestamp) -> Result<bool> {
+        let before = if schema.audit {
+            match self.inner.select_row_with_deleted(key, schema) {
+                Ok(row) => Some(self.stored_snapshot(key, schema, &row)?),
+                Err(Error::NotFound(_)) => None,
+                Err(err) => return Err(err),
+            }
+        } else {
+            None
+        };
+        let removed = if schema.soft_delete {
+            self.inner.set_deleted_at(key, schema, Some(now))?
+        } else {
+            for collection in &schema.collections {
+                self.inner.delete_collection(key, schema, collection)?;
+            }
+            self.inner.delete_row(key, schema)?
+        };
+        if removed && schema.audit {
+            self.record(schema, key, Operation::Delete, before, None);
+        }
+        Ok(removed)
+    }
+
+    /// Returns the recorded changes to the object of `T` with `key`, oldest
+    /// first. Changes made in this transaction are only recorded on commit.
+    pub fn history<T: Audited>(&self, key: T::Key) -> Result<Vec<HistoryEntry<T>>> {
+        let schema = T::schema();
+        if !self.inner.table_exists(HISTORY_TABLE)? {
+            return Ok(Vec::new());
+        }
+        let key = encode_values(&key.to_values());
+        let records = self.inner.select_history(Some((schema.table_name, &key)))?;
+        let mut entries = Vec::with_capacity(records.len());
+        for record in records {
+            let seq = record.seq;
+            let decode = |snapshot: Option<String>| match snapshot {
+                Some(snapshot) => self.decode_object::<T>(seq, &snapshot).map(Some),
+                None => Ok(None),
+            };
+            entries.push(HistoryEntry {
+                seq,
+                operation: record.operation,
+                before: decode(record.before)?,
+                after: decode(record.after)?,
+                changed_at: record.changed_at,
+                actor: record.actor,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn decode_object<T: Object>(&self, seq: u64, snapshot: &str) -> Result<T> {
+        let (row, collections) =
+            decode_snapshot(T::schema(), snapshot).ok_or(Error::CorruptHistory(seq))?;
+        let mut obj = T::from_row(row, collections)?;
+        obj.after_load()?;
+        Ok(obj)
+    }
+
+    /// Returns the object of `T` with `key` as it was at `at`, rebuilt from
+    /// the history of an `#[orm(audit)]` type. Fails with `NotFound` if it
+    /// did not exist then or was not audited yet. Only committed changes are
+    /// seen, and the returned object is not tracked by the transaction.
+    pub fn get_as_of<T: Audited>(&self, key: T::Key, at: Timestamp) -> Result<T> {
+        let values = key.to_values();
+        let encoded = encode_values(&values);
+        match self.history_as_of::<T>(Some(&encoded), at)?.pop() {
+            Some((seq, _, snapshot)) => self.decode_object(seq, &snapshot),
+            None => Err(not_found_error(T::schema(), &values)),
+        }
+    }
+
+    /// Selects the objects of `T` matching `clauses` as they were at `at`.
+    /// The clauses run against a temporary table holding the rebuilt rows,
+    /// which shadows the table of `T` meanwhile.
+    pub(crate) fn select_as_of<T: Audited>(
+        &self,
+        clauses: &Clauses,
+        at: Timestamp,
+    ) -> Result<Vec<T>> {
+        let schema = T::schema();
+        let mut rows = Vec::new();
+        let mut snapshots = HashMap::new();
+        for (seq, key, snapshot) in self.history_as_of::<T>(None, at)? {
+            let (row, _) = decode_snapshot(schema, &snapshot).ok_or(Error::CorruptHistory(seq))?;
+            rows.push((key.clone(), row));
+            snapshots.insert(key, (seq, snapshot));
+        }
+        self.inner.create_snapshot_table(schema, &rows)?;
+        let selected = self.inner.select_rows(schema, clauses);
+        self.inner.drop_snapshot_table(schema)?;
+        selected?
+            .into_iter()
+            .map(|(rowid, row)| {
+                let (seq, snapshot) = &snapshots[&key_values(schema, rowid, &row)];
+                self.decode_object(*seq, snapshot)
+            })
+            .collect()
+    }
+
+    /// The sequence number, key and snapshot of the last recorded state at
+    /// `at` of each object of `T` existing then, or of the object with the
+    /// encoded `key` only.
+    fn history_as_of<T: Audited>(
+        &self,
+        key: Option<&str>,
+        at: Timestamp,
+    ) -> Result<Vec<(u64, Row<'static>, String)>> {
+        let schema = T::schema();
+        if !self.inner.table_exists(HISTORY_TABLE)? {
+            return Ok(Vec::new());
+        }
+        let mut states = Vec::new();
+        for record in self
+            .inner
+            .select_history_as_of(schema.table_name, key, at)?
+        {
+            // Deleted objects have no state after their last change.
+            if let Some(snapshot) = record.after {
+                let key =
+                    decode_values(&record.object_key).ok_or(Error::CorruptHistory(record.seq))?;
+                states.push((record.seq, key, snapshot));
+            }
+        }
+        Ok(states)
+    }
+
+    /// Checks the hash chain of the history table. Returns the sequence number
+    /// of the first record that does not match its hash or the one before,
+    /// e.g. because it or its predecessor was altered or deleted, or `None` if
+    /// the history is intact. The chain only shows that the records are
+    /// consistent with each other: see `HistoryHead` for the changes it cannot
+    /// detect on its own.
+    pub fn verify_history(&self) -> Result<Option<u64>> {
+        self.verify_history_chain(None)
+    }
+
+    /// Like `verify_history`, but also checks that the history still holds
+    /// `head`, as returned by `history_head` earlier. Returns `head.seq` if
+    /// that record is missing or has another hash, e.g. because the records
+    /// up to it were rewritten or the last ones deleted.
+    pub fn verify_history_with(&self, head: &HistoryHead) -> Result<Option<u64>> {
+        self.verify_history_chain(Some(head))
+    }
+
+    /// The last record of the history, to be stored outside the database and
+    /// checked with `verify_history_with`, or `None` if there is none.
+    /// Changes made in this transaction are only recorded on commit.
+    pub fn history_head(&self) -> Result<Option<HistoryHead>> {
+        if !self.inner.table_exists(HISTORY_TABLE)? {
+            return Ok(None);
+        }
+        self.inner.history_head()
+    }
+
+    fn verify_history_chain(&self, head: Option<&HistoryHead>) -> Result<Option<u64>> {
+        let records = if self.inner.table_exists(HISTORY_TABLE)? {
+            self.inner.select_history(None)?
+        } else {
+            Vec::new()
+        };
+        let mut previous = String::new();
+        let mut found_head = false;
+        for record in records {
+            if record.chain_hash(&previous) != record.hash {
+                return Ok(Some(record.seq));
+            }
+            if let Some(head) = head {
+                if record.seq == head.seq {
+                    if record.hash != head.hash {
+                        return Ok(Some(head.seq));
+                    }
+                    found_head = true;
+                }
+            }
+            previous = record.hash;
+        }
+        match head {
+            Some(head) if !found_head => Ok(Some(head.seq)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Appends the changes recorded in this transaction to the history table,
+    /// all with the current time and actor.
+    fn write_history(&self) -> Result<()> {
+        let changes = self.changes.take();
+        if changes.is_empty() {
+            return Ok(());
+        }
+        if !self.inner.table_exists(HISTORY_TABLE)? {
+            self.inner.create_history_table()?;
+        }
+        let changed_at = self.clock.now();
+        let actor = self.actor.borrow().clone();
+        let mut previous = match self.inner.history_head()? {
+            Some(head) => head.hash,
+            None => String::new(),
+        };
+        for change in changes {
+            let mut record = HistoryRecord {
+                seq: 0,
+                object_type: change.schema.type_name.to_string(),
+                table_name: change.schema.table_name.to_string(),
+                object_key: change.key,
+                operation: change.operation,
+                before: change.before,
+                after: change.after,
+                changed_at,
+                actor: actor.clone(),
+                hash: String::new(),
+            };
+            record.hash = record.chain_hash(&previous);
+            self.inner.insert_history(&record)?;
+            previous = record.hash;
+        }
+        Ok(())
+    }
+
+    pub fn commit(self) -> Result<()> {
+        self.flush()?;
+        self.write_history()?;
         self.inner.commit()
     }
 
@@ -109,23 +1045,30 @@ impl<'a> Transaction<'a> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ObjectState {
     Clean,
     Modified,
     Removed,
 }
 
-#[derive(Clone)]
-pub struct Tx<'a, T> {
-    id: ObjectId,
-    data: Rc<RefCell<CacheValue<dyn Store>>>,
+pub struct Tx<'a, T: Object> {
+    key: T::Key,
+    data: Repr,
     lifetime: PhantomData<&'a T>,
 }
 
-impl<'a, T: Any> Tx<'a, T> {
-    pub fn id(&self) -> ObjectId {
-        self.id
+impl<'a, T: Object> Clone for Tx<'a, T> {
+    fn clone(&self) -> Self {
+        Tx::new(self.key.clone(), self.data.clone())
+    }
+}
+
+impl<'a, T: Object> Tx<'a, T> {
+    /// The primary key of the object: its `ObjectId` unless the type declares
+    /// `#[orm(primary_key)]` fields.
+    pub fn id(&self) -> T::Key {
+        self.key.clone()
     }
 
     pub fn state(&self) -> ObjectState {
@@ -146,6 +1089,8 @@ impl<'a, T: Any> Tx<'a, T> {
         RefMut::map(data, |r| r.obj.as_any_mut().downcast_mut().unwrap())
     }
 
+    /// Removes the object. Its row is deleted on the next flush or commit, or
+    /// marked as deleted for `#[orm(soft_delete)]` types.
     pub fn delete(self) {
         match self.data.try_borrow_mut() {
             Ok(mut data) => data.state = ObjectState::Removed,
@@ -161,10 +1106,10 @@ impl<'a, T: Any> Tx<'a, T> {
     }
 }
 
-impl<'a, T> Tx<'a, T> {
-    fn new(id: ObjectId, data: Rc<RefCell<CacheValue<dyn Store>>>) -> Self {
+impl<'a, T: Object> Tx<'a, T> {
+    fn new(key: T::Key, data: Repr) -> Self {
         Tx {
-            id,
+            key,
             data,
             lifetime: PhantomData,
         }
@@ -0,0 +1,133 @@
+use orm::{Connection, Error, Object, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug)]
+struct Purchase {
+    customer: String,
+    quantity: i32,
+    price: f32,
+}
+
+fn create_purchases(tx: &Transaction) {
+    for (customer, quantity, price) in [("ann", 2, 1.5), ("bob", 5, 2.0), ("ann", 1, 4.0)] {
+        tx.create(Purchase {
+            customer: customer.into(),
+            quantity,
+            price,
+        })
+        .unwrap();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn aggregates_over_matching_objects() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let c = Purchase::columns();
+    assert_eq!(tx.query::<Purchase>().count().unwrap(), 0);
+    assert_eq!(tx.query::<Purchase>().sum(c.quantity).unwrap(), 0);
+    assert_eq!(tx.query::<Purchase>().max(c.quantity).unwrap(), None);
+
+    create_purchases(&tx);
+    let ann = || tx.query::<Purchase>().filter(c.customer.eq("ann"));
+    assert_eq!(ann().count().unwrap(), 2);
+    assert_eq!(ann().sum(c.quantity).unwrap(), 3);
+    assert_eq!(ann().sum(c.price).unwrap(), 5.5);
+    assert_eq!(ann().min(c.price).unwrap(), Some(1.5));
+    assert_eq!(ann().avg(c.quantity).unwrap(), Some(1.5));
+    assert_eq!(
+        tx.query::<Purchase>()
+            .group_by(c.customer)
+            .sum(c.quantity)
+            .unwrap(),
+        [("ann".to_string(), 3), ("bob".to_string(), 5)]
+    );
+}
+
+#[test]
+fn aggregates_ignore_limit_and_offset() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_purchases(&tx);
+    let c = Purchase::columns();
+    let query = || tx.query::<Purchase>().order_by(c.quantity);
+    assert_eq!(query().offset(5).count().unwrap(), 3);
+    assert_eq!(query().limit(0).count().unwrap(), 3);
+    assert_eq!(query().limit(1).sum(c.quantity).unwrap(), 8);
+    assert_eq!(query().offset(3).max(c.quantity).unwrap(), Some(5));
+}
+
+#[test]
+fn sums_are_not_limited_to_the_column_type() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    for _ in 0..2 {
+        tx.create(Purchase {
+            customer: "ann".into(),
+            quantity: i32::MAX,
+            price: f32::MAX,
+        })
+        .unwrap();
+    }
+    let c = Purchase::columns();
+    assert_eq!(
+        tx.query::<Purchase>().sum(c.quantity).unwrap(),
+        2 * i32::MAX as i64
+    );
+    assert_eq!(
+        tx.query::<Purchase>().sum(c.price).unwrap(),
+        2.0 * f32::MAX as f64
+    );
+    let sums = tx
+        .query::<Purchase>()
+        .group_by(c.customer)
+        .sum(c.quantity)
+        .unwrap();
+    assert_eq!(sums, [("ann".to_string(), 2 * i32::MAX as i64)]);
+}
+
+#[test]
+fn groups_with_nulls() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let c = Purchase::columns();
+    create_purchases(&tx);
+    tx.execute_raw(
+        "INSERT INTO Purchase(customer, quantity, price) VALUES ('cid', NULL, NULL)",
+        &[],
+    )
+    .unwrap();
+    let by_customer = || tx.query::<Purchase>().group_by(c.customer);
+    let sums = by_customer().sum(c.quantity).unwrap();
+    assert_eq!(
+        sums,
+        [
+            ("ann".to_string(), 3),
+            ("bob".to_string(), 5),
+            ("cid".to_string(), 0)
+        ]
+    );
+    match by_customer().max(c.price) {
+        Err(Error::UnexpectedType(err)) => {
+            assert_eq!(err.column_name, "price");
+            assert_eq!(err.got_type, "Null");
+        }
+        other => panic!("expected UnexpectedType, got {:?}", other),
+    }
+
+    tx.execute_raw(
+        "UPDATE Purchase SET customer = NULL WHERE customer = 'cid'",
+        &[],
+    )
+    .unwrap();
+    match tx.query::<Purchase>().group_by(c.customer).count() {
+        Err(Error::UnexpectedType(err)) => {
+            assert_eq!(err.column_name, "customer");
+            assert_eq!(err.got_type, "Null");
+        }
+        other => panic!("expected UnexpectedType, got {:?}", other),
+    }
+}
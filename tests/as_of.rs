@@ -0,0 +1,130 @@
+mod common;
+
+use common::connection_at;
+use orm::{Connection, Error, Object, ObjectId, Timestamp};
+
+use std::cell::Cell;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+#[orm(soft_delete, audit)]
+struct Item {
+    name: String,
+    price: i64,
+}
+
+fn item(name: &str, price: i64) -> Item {
+    Item {
+        name: name.into(),
+        price,
+    }
+}
+
+fn at(micros: i64) -> Timestamp {
+    Timestamp::from_micros(micros)
+}
+
+/// Creates "a" at 100 and "b" at 200, raises the price of "a" at 300,
+/// deletes "b" at 400 and restores it at 500.
+fn create_history(conn: &mut Connection, now: &Cell<i64>) -> (ObjectId, ObjectId) {
+    let tx = conn.new_transaction().unwrap();
+    let a = tx.create(item("a", 1)).unwrap().id();
+    tx.commit().unwrap();
+    now.set(200);
+    let tx = conn.new_transaction().unwrap();
+    let b = tx.create(item("b", 2)).unwrap().id();
+    tx.commit().unwrap();
+    now.set(300);
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Item>(a).unwrap().borrow_mut().price = 3;
+    tx.commit().unwrap();
+    now.set(400);
+    let tx = conn.new_transaction().unwrap();
+    tx.delete_by_id::<Item>(b).unwrap();
+    tx.commit().unwrap();
+    now.set(500);
+    let tx = conn.new_transaction().unwrap();
+    tx.restore::<Item>(b).unwrap();
+    tx.commit().unwrap();
+    (a, b)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn get_as_of_rebuilds_past_states() {
+    let (mut conn, now) = connection_at(100);
+    let (a, b) = create_history(&mut conn, &now);
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get_as_of::<Item>(a, at(50)),
+        Err(Error::NotFound(_))
+    ));
+    assert_eq!(tx.get_as_of::<Item>(a, at(100)).unwrap(), item("a", 1));
+    assert_eq!(tx.get_as_of::<Item>(a, at(299)).unwrap(), item("a", 1));
+    assert_eq!(tx.get_as_of::<Item>(a, at(300)).unwrap(), item("a", 3));
+    assert_eq!(tx.get_as_of::<Item>(b, at(350)).unwrap(), item("b", 2));
+    assert!(matches!(
+        tx.get_as_of::<Item>(b, at(450)),
+        Err(Error::NotFound(_))
+    ));
+    assert_eq!(tx.get_as_of::<Item>(b, at(500)).unwrap(), item("b", 2));
+}
+
+#[test]
+fn fetch_as_of_queries_past_states() {
+    let (mut conn, now) = connection_at(100);
+    create_history(&mut conn, &now);
+    let tx = conn.new_transaction().unwrap();
+    let c = Item::columns();
+    let names = |micros| {
+        tx.query::<Item>()
+            .order_by(c.name)
+            .fetch_as_of(at(micros))
+            .unwrap()
+    };
+    assert!(names(50).is_empty());
+    assert_eq!(names(150), [item("a", 1)]);
+    assert_eq!(names(250), [item("a", 1), item("b", 2)]);
+    assert_eq!(names(300), [item("a", 3), item("b", 2)]);
+    assert_eq!(names(400), [item("a", 3)]);
+    assert_eq!(names(600), [item("a", 3), item("b", 2)]);
+
+    let cheap = tx
+        .query::<Item>()
+        .filter(c.price.lt(&3i64))
+        .fetch_as_of(at(250))
+        .unwrap();
+    assert_eq!(cheap.len(), 2);
+
+    // Live reads and writes still use the table.
+    tx.create(item("c", 4)).unwrap();
+    assert_eq!(tx.query::<Item>().count().unwrap(), 3);
+}
+
+#[test]
+fn failed_reads_as_of_leave_the_table_visible() {
+    let (mut conn, now) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let ids = tx
+        .create_many((1..=11).map(|i| item("x", i)))
+        .unwrap()
+        .iter()
+        .map(|obj| obj.id())
+        .collect::<Vec<_>>();
+    tx.commit().unwrap();
+
+    // A tampered key of item 11 that decodes like the key of item 10.
+    let tx = conn.new_transaction().unwrap();
+    now.set(200);
+    let sql = "UPDATE orm_history SET object_key = upper( \
+               (SELECT object_key FROM orm_history WHERE seq = 10)) WHERE seq = 11";
+    tx.execute_raw(sql, &[]).unwrap();
+    let result = tx.query::<Item>().fetch_as_of(at(150));
+    assert!(matches!(result, Err(Error::ConstraintViolation(_))));
+
+    tx.create(item("y", 12)).unwrap();
+    assert_eq!(tx.query::<Item>().count().unwrap(), 12);
+    assert_eq!(tx.get::<Item>(ids[10]).unwrap().borrow().price, 11);
+}
@@ -0,0 +1,224 @@
+use orm::{Connection, Error, Object, ObjectState};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+struct Person {
+    name: String,
+    age: i64,
+    active: bool,
+}
+
+fn person(name: &str, age: i64) -> Person {
+    Person {
+        name: name.into(),
+        age,
+        active: true,
+    }
+}
+
+fn create_people(conn: &mut Connection) {
+    let tx = conn.new_transaction().unwrap();
+    for (name, age) in [("ann", 25), ("bob", 30), ("cid", 35), ("dan", 40)] {
+        tx.create(person(name, age)).unwrap();
+    }
+    tx.commit().unwrap();
+}
+
+fn names(conn: &mut Connection) -> Vec<String> {
+    let tx = conn.new_transaction().unwrap();
+    let people = tx
+        .query::<Person>()
+        .order_by(Person::columns().name)
+        .fetch()
+        .unwrap();
+    let names = people.iter().map(|p| p.borrow().name.clone()).collect();
+    names
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn affected_row_counts() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    create_people(&mut conn);
+    let c = Person::columns();
+
+    let tx = conn.new_transaction().unwrap();
+    let update = tx.update::<Person>().filter(c.age.ge(&30i64));
+    assert_eq!(update.set(c.active, &false).execute().unwrap(), 3);
+    let update = tx.update::<Person>().filter(c.name.eq("eve"));
+    assert_eq!(update.set(c.age, &50i64).execute().unwrap(), 0);
+    let delete = tx.delete::<Person>().filter(c.active.eq(&false));
+    assert_eq!(delete.execute().unwrap(), 3);
+    assert_eq!(
+        tx.delete::<Person>()
+            .filter(c.age.gt(&30i64))
+            .execute()
+            .unwrap(),
+        0
+    );
+    tx.commit().unwrap();
+
+    assert_eq!(names(&mut conn), ["ann"]);
+}
+
+#[test]
+fn filter_composition() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    create_people(&mut conn);
+    let c = Person::columns();
+
+    let tx = conn.new_transaction().unwrap();
+    let filter = c.age.lt(&30i64).or(c.age.gt(&35i64));
+    assert_eq!(tx.query::<Person>().filter(filter).count().unwrap(), 2);
+    let filter = !c.name.eq("bob").and(c.age.le(&35i64));
+    assert_eq!(tx.query::<Person>().filter(filter).count().unwrap(), 3);
+
+    // Successive filters all apply.
+    let update = tx
+        .update::<Person>()
+        .filter(c.age.ge(&30i64))
+        .filter(c.name.ne("cid"))
+        .set(c.active, &false)
+        .set(c.age, &0i64);
+    assert_eq!(update.execute().unwrap(), 2);
+    let delete = tx
+        .delete::<Person>()
+        .filter(c.active.eq(&false))
+        .filter(c.name.eq("bob").or(c.name.eq("cid")));
+    assert_eq!(delete.execute().unwrap(), 1);
+    tx.commit().unwrap();
+
+    assert_eq!(names(&mut conn), ["ann", "cid", "dan"]);
+    let tx = conn.new_transaction().unwrap();
+    let dan = tx.get_by(c.name, "dan").unwrap();
+    assert_eq!((dan.borrow().age, dan.borrow().active), (0, false));
+}
+
+#[test]
+fn cached_objects_are_patched_on_update() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    create_people(&mut conn);
+    let c = Person::columns();
+
+    let tx = conn.new_transaction().unwrap();
+    let bob = tx.get_by(c.name, "bob").unwrap();
+    let ann = tx.get_by(c.name, "ann").unwrap();
+    ann.borrow_mut().age = 26;
+    let update = tx.update::<Person>().filter(c.age.lt(&35i64));
+    assert_eq!(update.set(c.active, &false).execute().unwrap(), 2);
+    assert_eq!(
+        *bob.borrow(),
+        Person {
+            active: false,
+            ..person("bob", 30)
+        }
+    );
+    assert_eq!(
+        *ann.borrow(),
+        Person {
+            active: false,
+            ..person("ann", 26)
+        }
+    );
+    assert_eq!(ann.state(), ObjectState::Clean);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let ann = tx.get_by(c.name, "ann").unwrap();
+    assert_eq!(
+        *ann.borrow(),
+        Person {
+            active: false,
+            ..person("ann", 26)
+        }
+    );
+}
+
+#[test]
+fn cached_objects_are_evicted_on_delete() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    create_people(&mut conn);
+    let c = Person::columns();
+
+    let tx = conn.new_transaction().unwrap();
+    let bob = tx.get_by(c.name, "bob").unwrap();
+    let cid = tx.get_by(c.name, "cid").unwrap();
+    let id = bob.id();
+    assert_eq!(
+        tx.delete::<Person>()
+            .filter(c.age.le(&30i64))
+            .execute()
+            .unwrap(),
+        2
+    );
+    assert_eq!(bob.state(), ObjectState::Removed);
+    assert_eq!(cid.state(), ObjectState::Clean);
+    assert!(matches!(tx.get::<Person>(id), Err(Error::NotFound(_))));
+    assert!(tx.find_one(c.name, "ann").unwrap().is_none());
+    tx.commit().unwrap();
+
+    assert_eq!(names(&mut conn), ["cid", "dan"]);
+}
+
+#[test]
+fn update_with_borrowed_objects() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    create_people(&mut conn);
+    let c = Person::columns();
+
+    let tx = conn.new_transaction().unwrap();
+    let ann = tx.get_by(c.name, "ann").unwrap();
+    let dan = tx.get_by(c.name, "dan").unwrap();
+    let borrowed = dan.borrow();
+    let update = tx.update::<Person>().filter(c.age.lt(&30i64));
+    assert_eq!(update.set(c.age, &20i64).execute().unwrap(), 1);
+    assert_eq!(ann.borrow().age, 20);
+    let update = tx.update::<Person>().filter(c.age.ge(&30i64));
+    match update.set(c.age, &60i64).execute() {
+        Err(Error::ObjectBorrowed) => {}
+        other => panic!("expected ObjectBorrowed, got {:?}", other),
+    }
+    drop(borrowed);
+    assert_eq!(dan.borrow().age, 40);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let ages = tx
+        .query::<Person>()
+        .order_by(c.name)
+        .fetch()
+        .unwrap()
+        .iter()
+        .map(|p| p.borrow().age)
+        .collect::<Vec<_>>();
+    assert_eq!(ages, [20, 30, 35, 40]);
+}
+
+#[test]
+fn delete_with_borrowed_objects() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    create_people(&mut conn);
+    let c = Person::columns();
+
+    let tx = conn.new_transaction().unwrap();
+    let dan = tx.get_by(c.name, "dan").unwrap();
+    let borrowed = dan.borrow();
+    assert_eq!(
+        tx.delete::<Person>()
+            .filter(c.age.lt(&30i64))
+            .execute()
+            .unwrap(),
+        1
+    );
+    match tx.delete::<Person>().execute() {
+        Err(Error::ObjectBorrowed) => {}
+        other => panic!("expected ObjectBorrowed, got {:?}", other),
+    }
+    drop(borrowed);
+    assert_eq!(dan.state(), ObjectState::Clean);
+    tx.commit().unwrap();
+
+    assert_eq!(names(&mut conn), ["bob", "cid", "dan"]);
+}
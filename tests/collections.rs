@@ -0,0 +1,134 @@
+use orm::{Connection, Embeddable, FromRow, Object, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Embeddable, Clone, Debug, PartialEq)]
+struct Phone {
+    kind: String,
+    number: String,
+}
+
+#[derive(Object, Clone, Debug, PartialEq)]
+struct Contact {
+    name: String,
+    tags: Vec<String>,
+    scores: Vec<i32>,
+    phones: Vec<Phone>,
+}
+
+#[derive(FromRow)]
+struct Count {
+    n: i64,
+}
+
+fn phone(kind: &str, number: &str) -> Phone {
+    Phone {
+        kind: kind.into(),
+        number: number.into(),
+    }
+}
+
+fn contact() -> Contact {
+    Contact {
+        name: "Ada".into(),
+        tags: vec!["vip".into(), "eu".into(), "vip".into()],
+        scores: vec![3, 1, 2],
+        phones: vec![phone("home", "123"), phone("work", "456")],
+    }
+}
+
+fn count_rows(tx: &Transaction, table: &str) -> i64 {
+    let sql = format!("SELECT COUNT(*) AS n FROM {}", table);
+    tx.query_as::<Count>(&sql, &[]).unwrap()[0].n
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn collections_round_trip_in_order() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(contact()).unwrap().id();
+    let empty = tx
+        .create(Contact {
+            name: "Bob".into(),
+            tags: Vec::new(),
+            scores: Vec::new(),
+            phones: Vec::new(),
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(*tx.get::<Contact>(id).unwrap().borrow(), contact());
+    let empty = tx.get::<Contact>(empty).unwrap();
+    assert!(empty.borrow().tags.is_empty());
+    assert!(empty.borrow().phones.is_empty());
+}
+
+#[test]
+fn collections_are_replaced_on_update() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(contact()).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    {
+        let obj = tx.get::<Contact>(id).unwrap();
+        let mut obj = obj.borrow_mut();
+        obj.tags = vec!["new".into()];
+        obj.scores.push(4);
+        obj.phones.remove(0);
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.get::<Contact>(id).unwrap();
+    assert_eq!(obj.borrow().tags, ["new"]);
+    assert_eq!(obj.borrow().scores, [3, 1, 2, 4]);
+    assert_eq!(obj.borrow().phones, [phone("work", "456")]);
+    assert_eq!(count_rows(&tx, "Contact_tags"), 1);
+    assert_eq!(count_rows(&tx, "Contact_phones"), 1);
+}
+
+#[test]
+fn collections_are_deleted_with_the_owner() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let first = tx.create(contact()).unwrap().id();
+    let second = tx.create(contact()).unwrap().id();
+    tx.create(contact()).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Contact>(first).unwrap().delete();
+    tx.delete_by_id::<Contact>(second).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(count_rows(&tx, "Contact_tags"), 3);
+    assert_eq!(count_rows(&tx, "Contact_scores"), 3);
+    assert_eq!(count_rows(&tx, "Contact_phones"), 2);
+
+    tx.delete::<Contact>().execute().unwrap();
+    assert_eq!(count_rows(&tx, "Contact_tags"), 0);
+    assert_eq!(count_rows(&tx, "Contact_scores"), 0);
+    assert_eq!(count_rows(&tx, "Contact_phones"), 0);
+}
+
+#[test]
+fn collections_cascade_with_raw_deletes() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(contact()).unwrap();
+    tx.create(contact()).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.execute_raw("DELETE FROM Contact WHERE id = 1", &[])
+        .unwrap();
+    assert_eq!(count_rows(&tx, "Contact_tags"), 3);
+    assert_eq!(count_rows(&tx, "Contact_phones"), 2);
+}
@@ -0,0 +1,14 @@
+use orm::{Connection, Timestamp};
+
+use std::{cell::Cell, rc::Rc};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A connection whose clock reads the returned cell, in microseconds.
+pub fn connection_at(micros: i64) -> (Connection, Rc<Cell<i64>>) {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let now = Rc::new(Cell::new(micros));
+    let clock = now.clone();
+    conn.set_clock(move || Timestamp::from_micros(clock.get()));
+    (conn, now)
+}
@@ -0,0 +1,96 @@
+use orm::{Connection, Error, Object, Tx};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Clone, Debug)]
+#[orm(unique(team, number), index(team, name), check = "number <> 13")]
+struct Player {
+    #[orm(unique)]
+    email: String,
+    #[orm(index)]
+    name: String,
+    team: String,
+    #[orm(check = "number > 0")]
+    number: i64,
+}
+
+fn player(email: &str, team: &str, number: i64) -> Player {
+    Player {
+        email: email.into(),
+        name: "Ann".into(),
+        team: team.into(),
+        number,
+    }
+}
+
+fn assert_violates<T: Object>(result: orm::Result<Tx<'_, T>>, constraint: &str) {
+    match result {
+        Err(Error::ConstraintViolation(err)) => {
+            assert_eq!(err.table_name, "Player");
+            assert_eq!(err.constraint, constraint);
+        }
+        Err(other) => panic!("expected ConstraintViolation, got {:?}", other),
+        Ok(_) => panic!("expected ConstraintViolation on {}", constraint),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn constraints_are_recorded_in_the_schema() {
+    let schema = Player::schema();
+    let indexes = schema
+        .indexes
+        .iter()
+        .map(|i| (i.name, i.unique))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        indexes,
+        [
+            ("Player_email_key", true),
+            ("Player_name_idx", false),
+            ("Player_team_number_key", true),
+            ("Player_team_name_idx", false),
+        ]
+    );
+    let checks = schema
+        .checks
+        .iter()
+        .map(|c| (c.name, c.expr))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        checks,
+        [
+            ("Player_number_check", "number > 0"),
+            ("Player_check_1", "number <> 13"),
+        ]
+    );
+}
+
+#[test]
+fn violations_name_the_constraint() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(player("a@x", "red", 7)).unwrap();
+
+    assert_violates(tx.create(player("a@x", "blue", 7)), "Player_email_key");
+    assert_violates(tx.create(player("b@x", "red", 7)), "Player_team_number_key");
+    assert_violates(tx.create(player("b@x", "red", 0)), "Player_number_check");
+    assert_violates(tx.create(player("b@x", "red", 13)), "Player_check_1");
+    tx.create(player("b@x", "blue", 7)).unwrap();
+}
+
+#[test]
+fn violations_on_flush_name_the_constraint() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(player("a@x", "red", 7)).unwrap();
+    let other = tx.create(player("b@x", "red", 8)).unwrap();
+    other.borrow_mut().email = "a@x".into();
+    match tx.commit() {
+        Err(Error::ConstraintViolation(err)) => {
+            assert_eq!(err.constraint, "Player_email_key")
+        }
+        other => panic!("expected ConstraintViolation, got {:?}", other),
+    }
+}
@@ -0,0 +1,83 @@
+use orm::{Connection, Error, Object, ObjectId};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+struct Reading {
+    sensor: String,
+    value: f64,
+    samples: Vec<i64>,
+}
+
+#[derive(Object, Debug, PartialEq)]
+struct Sensor {
+    #[orm(primary_key)]
+    code: String,
+    #[orm(unique)]
+    serial: i64,
+}
+
+fn reading(i: i64) -> Reading {
+    Reading {
+        sensor: format!("s{}", i % 3),
+        value: i as f64 / 2.0,
+        samples: vec![i, i + 1],
+    }
+}
+
+fn sensor(code: &str, serial: i64) -> Sensor {
+    Sensor {
+        code: code.into(),
+        serial,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn create_many_returns_handles_in_order() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let txs = tx.create_many((0..100).map(reading)).unwrap();
+    let ids = txs.iter().map(|t| t.id()).collect::<Vec<_>>();
+    assert_eq!(ids, (1..=100).map(ObjectId::from).collect::<Vec<_>>());
+    assert_eq!(*txs[41].borrow(), reading(41));
+    assert_eq!(
+        *tx.get::<Reading>(ObjectId::from(42)).unwrap().borrow(),
+        reading(41)
+    );
+    assert!(tx.create_many(Vec::<Reading>::new()).unwrap().is_empty());
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.query::<Reading>().count().unwrap(), 100);
+    assert_eq!(
+        *tx.get::<Reading>(ObjectId::from(100)).unwrap().borrow(),
+        reading(99)
+    );
+}
+
+#[test]
+fn create_many_with_natural_keys() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let txs = tx.create_many([sensor("a", 1), sensor("b", 2)]).unwrap();
+    assert_eq!(txs[1].id(), "b");
+    assert_eq!(
+        tx.get::<Sensor>("a".to_string()).unwrap().borrow().serial,
+        1
+    );
+}
+
+#[test]
+fn create_many_stops_at_the_first_failure() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let result = tx.create_many([sensor("a", 1), sensor("b", 1), sensor("c", 3)]);
+    assert!(matches!(result, Err(Error::ConstraintViolation(_))));
+    tx.rollback().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.create(sensor("z", 9)).unwrap();
+    assert_eq!(tx.query::<Sensor>().count().unwrap(), 1);
+}
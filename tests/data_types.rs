@@ -0,0 +1,212 @@
+use orm::{
+    data::{DataType, Value},
+    Connection, Error, FromRow, Object,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Clone, Debug, Default, PartialEq)]
+struct Narrow {
+    i8_value: i8,
+    i16_value: i16,
+    i32_value: i32,
+    u8_value: u8,
+    u16_value: u16,
+    u32_value: u32,
+    u64_value: u64,
+    f32_value: f32,
+}
+
+#[derive(FromRow, Debug)]
+#[allow(dead_code)]
+struct Levels {
+    i8_value: i8,
+    u16_value: u16,
+}
+
+fn assert_unexpected_type<T: std::fmt::Debug>(result: orm::Result<T>, type_name: &str) {
+    match result {
+        Err(Error::UnexpectedType(err)) => {
+            assert_eq!(err.type_name, type_name);
+            assert_eq!(err.attr_name, "u16_value");
+            assert_eq!(err.expected_type, DataType::UInt16);
+            assert_eq!(err.got_type, "Text");
+        }
+        other => panic!("expected UnexpectedType, got {:?}", other),
+    }
+}
+
+/// Stores `value` in `column` of a new object behind the ORM's back, then
+/// loads the object.
+fn load_with(column: &str, value: &str) -> orm::Result<Narrow> {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(Narrow::default()).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let sql = format!("UPDATE Narrow SET {} = {}", column, value);
+    assert_eq!(tx.execute_raw(&sql, &[]).unwrap(), 1);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.get::<Narrow>(id)?.borrow().clone();
+    Ok(obj)
+}
+
+fn assert_out_of_range(column: &str, value: &str) {
+    match load_with(column, value) {
+        Err(Error::OutOfRange(err)) => {
+            assert_eq!(err.column_name, column);
+            assert_eq!(err.type_name, "Narrow");
+        }
+        other => panic!(
+            "{} = {}: expected OutOfRange, got {:?}",
+            column, value, other
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn narrow_integers_at_their_bounds() {
+    let obj = load_with("i8_value", "-128").unwrap();
+    assert_eq!(obj.i8_value, i8::MIN);
+    assert_eq!(load_with("i8_value", "127").unwrap().i8_value, i8::MAX);
+    assert_eq!(
+        load_with("i16_value", "-32768").unwrap().i16_value,
+        i16::MIN
+    );
+    assert_eq!(load_with("i16_value", "32767").unwrap().i16_value, i16::MAX);
+    assert_eq!(
+        load_with("i32_value", "-2147483648").unwrap().i32_value,
+        i32::MIN
+    );
+    assert_eq!(
+        load_with("i32_value", "2147483647").unwrap().i32_value,
+        i32::MAX
+    );
+    assert_eq!(load_with("u8_value", "255").unwrap().u8_value, u8::MAX);
+    assert_eq!(load_with("u16_value", "65535").unwrap().u16_value, u16::MAX);
+    assert_eq!(
+        load_with("u32_value", "4294967295").unwrap().u32_value,
+        u32::MAX
+    );
+    assert_eq!(
+        load_with("u64_value", "9223372036854775807")
+            .unwrap()
+            .u64_value,
+        i64::MAX as u64
+    );
+    assert_eq!(
+        load_with("f32_value", "3.4028234663852886e38")
+            .unwrap()
+            .f32_value,
+        f32::MAX
+    );
+}
+
+#[test]
+fn narrow_integers_out_of_range() {
+    assert_out_of_range("i8_value", "-129");
+    assert_out_of_range("i8_value", "128");
+    assert_out_of_range("i16_value", "-32769");
+    assert_out_of_range("i16_value", "32768");
+    assert_out_of_range("i32_value", "-2147483649");
+    assert_out_of_range("i32_value", "2147483648");
+    assert_out_of_range("u8_value", "-1");
+    assert_out_of_range("u8_value", "256");
+    assert_out_of_range("u16_value", "-1");
+    assert_out_of_range("u16_value", "65536");
+    assert_out_of_range("u32_value", "-1");
+    assert_out_of_range("u32_value", "4294967296");
+    assert_out_of_range("u64_value", "-1");
+}
+
+#[test]
+fn f32_out_of_range() {
+    assert_out_of_range("f32_value", "1e300");
+    assert_out_of_range("f32_value", "-1e300");
+}
+
+#[test]
+fn u64_above_i64_max_is_rejected_on_write() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let result = tx.create(Narrow {
+        u64_value: i64::MAX as u64 + 1,
+        ..Default::default()
+    });
+    match result {
+        Err(Error::OutOfRange(err)) => {
+            assert_eq!(err.column_name, "u64_value");
+            assert_eq!(err.value, (i64::MAX as u64 + 1).to_string());
+        }
+        Err(other) => panic!("expected OutOfRange, got {:?}", other),
+        Ok(_) => panic!("expected OutOfRange"),
+    }
+}
+
+#[test]
+fn mismatched_column_type_is_reported_with_the_field() {
+    match load_with("i32_value", "'forty-two'") {
+        Err(Error::UnexpectedType(err)) => {
+            assert_eq!(err.type_name, "Narrow");
+            assert_eq!(err.attr_name, "i32_value");
+            assert_eq!(err.table_name, "Narrow");
+            assert_eq!(err.column_name, "i32_value");
+            assert_eq!(err.expected_type, DataType::Int32);
+            assert_eq!(err.got_type, "Text");
+        }
+        other => panic!("expected UnexpectedType, got {:?}", other),
+    }
+}
+
+#[test]
+fn mismatched_value_type_is_reported_with_the_field() {
+    let obj = Narrow::default();
+    let mut row = obj.to_row();
+    row[2] = Value::from("forty-two");
+    match Narrow::from_row(row, Vec::new()) {
+        Err(Error::UnexpectedType(err)) => {
+            assert_eq!(err.attr_name, "i32_value");
+            assert_eq!(err.expected_type, DataType::Int32);
+        }
+        other => panic!("expected UnexpectedType, got {:?}", other),
+    }
+}
+
+#[test]
+fn mismatched_types_in_raw_queries_are_reported_with_the_field() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Narrow::default()).unwrap();
+    tx.execute_raw("UPDATE Narrow SET u16_value = 'x'", &[])
+        .unwrap();
+
+    let sql = "SELECT id, f32_value, u64_value, u32_value, u16_value, u8_value, \
+               i32_value, i16_value, i8_value FROM Narrow";
+    assert_unexpected_type(tx.query_raw_detached::<Narrow>(sql, &[]), "Narrow");
+    let sql = "SELECT i8_value, u16_value FROM Narrow";
+    assert_unexpected_type(tx.query_as::<Levels>(sql, &[]), "Levels");
+
+    let c = Narrow::columns();
+    assert_unexpected_type(tx.query::<Narrow>().select(c.u16_value), "Narrow");
+    assert_unexpected_type(tx.query::<Narrow>().max(c.u16_value), "Narrow");
+}
+
+#[test]
+fn out_of_range_values_in_raw_queries_are_reported_with_the_field() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Narrow::default()).unwrap();
+    tx.execute_raw("UPDATE Narrow SET u8_value = 300", &[])
+        .unwrap();
+    let sql = "SELECT u8_value, id, i8_value, i16_value, i32_value, u16_value, \
+               u32_value, u64_value, f32_value FROM Narrow";
+    match tx.query_raw_detached::<Narrow>(sql, &[]) {
+        Err(Error::OutOfRange(err)) => assert_eq!(err.column_name, "u8_value"),
+        other => panic!("expected OutOfRange, got {:?}", other),
+    }
+}
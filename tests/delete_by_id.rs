@@ -0,0 +1,111 @@
+use orm::{Connection, Error, Object, ObjectId, ObjectState};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+struct Note {
+    text: String,
+}
+
+fn note(text: &str) -> Note {
+    Note { text: text.into() }
+}
+
+fn assert_not_found<T>(result: orm::Result<T>, key: &str) {
+    match result {
+        Err(Error::NotFound(err)) => assert_eq!(err.key, key),
+        Err(other) => panic!("expected NotFound, got {:?}", other),
+        Ok(_) => panic!("expected NotFound for {}", key),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn delete_without_loading() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(note("a")).unwrap().id();
+    tx.create(note("b")).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.delete_by_id::<Note>(id).unwrap();
+    assert_not_found(tx.delete_by_id::<Note>(id), "id = 1");
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_not_found(tx.get::<Note>(id), "id = 1");
+    assert_eq!(tx.query::<Note>().count().unwrap(), 1);
+}
+
+#[test]
+fn delete_loaded_object_by_id() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.create(note("a")).unwrap();
+    tx.delete_by_id::<Note>(obj.id()).unwrap();
+    assert_eq!(obj.state(), ObjectState::Removed);
+    assert_not_found(tx.delete_by_id::<Note>(obj.id()), "id = 1");
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.query::<Note>().count().unwrap(), 0);
+}
+
+#[test]
+fn missing_rows_are_reported_after_applying_all_deletes() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    for text in ["a", "b", "c"] {
+        tx.create(note(text)).unwrap();
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.delete_by_id::<Note>(ObjectId::from(1)).unwrap();
+    tx.delete_by_id::<Note>(ObjectId::from(9)).unwrap();
+    tx.delete_by_id::<Note>(ObjectId::from(3)).unwrap();
+    assert_not_found(tx.flush(), "id = 9");
+    tx.flush().unwrap();
+    let notes = tx.query::<Note>().fetch().unwrap();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(*notes[0].borrow(), note("b"));
+}
+
+#[test]
+fn missing_rows_are_reported_by_the_next_flush() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(note("a")).unwrap();
+    tx.delete_by_id::<Note>(ObjectId::from(5)).unwrap();
+    assert_not_found(tx.query::<Note>().count(), "id = 5");
+    assert_eq!(tx.query::<Note>().count().unwrap(), 1);
+
+    tx.delete_by_id::<Note>(ObjectId::from(6)).unwrap();
+    assert_not_found(tx.commit(), "id = 6");
+}
+
+#[test]
+fn delete_borrowed_object_by_id() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(note("a")).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.get::<Note>(id).unwrap();
+    let borrowed = obj.borrow();
+    assert!(matches!(
+        tx.delete_by_id::<Note>(id),
+        Err(Error::ObjectBorrowed)
+    ));
+    drop(borrowed);
+    assert_eq!(obj.state(), ObjectState::Clean);
+    tx.delete_by_id::<Note>(id).unwrap();
+    assert_eq!(obj.state(), ObjectState::Removed);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_not_found(tx.get::<Note>(id), "id = 1");
+}
@@ -0,0 +1,148 @@
+use orm::{
+    data::{ToDataType, Value, ValueError},
+    Connection, Embeddable, Object,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Embeddable, Clone, Debug, PartialEq)]
+struct Point {
+    lat: f64,
+    lon: f64,
+}
+
+#[derive(Embeddable, Clone, Debug, PartialEq)]
+struct Address {
+    city: String,
+    zip: String,
+    #[flatten]
+    location: Point,
+}
+
+#[derive(Object, Clone, Debug, PartialEq)]
+struct Customer {
+    name: String,
+    #[flatten]
+    home: Address,
+    #[flatten]
+    #[column_name("work")]
+    office: Address,
+}
+
+#[derive(Embeddable, Clone, Debug, PartialEq)]
+struct Span<V>
+where
+    V: ToDataType + for<'v> TryFrom<Value<'v>, Error = ValueError> + 'static,
+    for<'v> Value<'v>: From<&'v V>,
+{
+    low: V,
+    high: V,
+}
+
+#[derive(Object, Clone, Debug, PartialEq)]
+struct Range<V>
+where
+    V: ToDataType + for<'v> TryFrom<Value<'v>, Error = ValueError> + 'static,
+    for<'v> Value<'v>: From<&'v V>,
+{
+    label: String,
+    #[flatten]
+    span: Span<V>,
+}
+
+fn address(city: &str, zip: &str) -> Address {
+    Address {
+        city: city.into(),
+        zip: zip.into(),
+        location: Point {
+            lat: 1.5,
+            lon: -2.5,
+        },
+    }
+}
+
+fn customer() -> Customer {
+    Customer {
+        name: "Ada".into(),
+        home: address("London", "N1"),
+        office: address("Cambridge", "CB2"),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn flattened_columns_are_prefixed() {
+    let columns = Customer::schema()
+        .fields
+        .iter()
+        .map(|f| (f.column_name, f.attr_name))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        columns,
+        [
+            ("name", "name"),
+            ("home_city", "home.city"),
+            ("home_zip", "home.zip"),
+            ("home_location_lat", "home.location.lat"),
+            ("home_location_lon", "home.location.lon"),
+            ("work_city", "office.city"),
+            ("work_zip", "office.zip"),
+            ("work_location_lat", "office.location.lat"),
+            ("work_location_lon", "office.location.lon"),
+        ]
+    );
+}
+
+#[test]
+fn flattened_fields_round_trip() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(customer()).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.get::<Customer>(id).unwrap();
+    assert_eq!(*obj.borrow(), customer());
+    obj.borrow_mut().office.location.lat = 52.2;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.get::<Customer>(id).unwrap();
+    assert_eq!(obj.borrow().office.location.lat, 52.2);
+    assert_eq!(obj.borrow().home, address("London", "N1"));
+}
+
+#[test]
+fn flattened_columns_are_stored_inline() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(customer()).unwrap();
+    let found = tx
+        .query_raw::<Customer>(
+            "SELECT * FROM Customer WHERE work_city = 'Cambridge' AND home_location_lon < 0",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].borrow().name, "Ada");
+}
+
+#[test]
+fn generic_objects_flatten_generic_embeddables() {
+    let columns = Range::<i64>::schema()
+        .fields
+        .iter()
+        .map(|f| f.column_name)
+        .collect::<Vec<_>>();
+    assert_eq!(columns, ["label", "span_low", "span_high"]);
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let range = Range {
+        label: "week".into(),
+        span: Span { low: 1i64, high: 7 },
+    };
+    let id = tx.create(range.clone()).unwrap().id();
+    assert_eq!(*tx.get::<Range<i64>>(id).unwrap().borrow(), range);
+}
@@ -0,0 +1,87 @@
+use orm::{Connection, Object};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+struct Setting {
+    name: String,
+    #[orm(default = "n/a")]
+    value: String,
+    #[orm(default = -1)]
+    priority: i64,
+    #[orm(default = 0.5)]
+    weight: f64,
+    #[orm(default = true)]
+    enabled: bool,
+    #[orm(skip)]
+    cache: Option<String>,
+}
+
+fn setting(name: &str) -> Setting {
+    Setting {
+        name: name.into(),
+        value: "on".into(),
+        priority: 3,
+        weight: 1.0,
+        enabled: false,
+        cache: Some("computed".into()),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn skipped_fields_are_not_columns() {
+    let columns = Setting::schema()
+        .fields
+        .iter()
+        .map(|f| f.column_name)
+        .collect::<Vec<_>>();
+    assert_eq!(columns, ["name", "value", "priority", "weight", "enabled"]);
+}
+
+#[test]
+fn skipped_fields_load_as_default() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.create(setting("theme")).unwrap();
+    let id = obj.id();
+    assert_eq!(obj.borrow().cache.as_deref(), Some("computed"));
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.get::<Setting>(id).unwrap();
+    assert_eq!(obj.borrow().cache, None);
+    assert_eq!(obj.borrow().value, "on");
+}
+
+#[test]
+fn defaults_are_declared_in_the_table() {
+    let defaults = Setting::schema()
+        .fields
+        .iter()
+        .map(|f| f.default)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        defaults,
+        [None, Some("'n/a'"), Some("-1"), Some("0.5"), Some("1")]
+    );
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(setting("theme")).unwrap();
+    tx.execute_raw("INSERT INTO Setting(name) VALUES('font')", &[])
+        .unwrap();
+    let obj = tx.get_by(Setting::columns().name, "font").unwrap();
+    assert_eq!(
+        *obj.borrow(),
+        Setting {
+            name: "font".into(),
+            value: "n/a".into(),
+            priority: -1,
+            weight: 0.5,
+            enabled: true,
+            cache: None,
+        }
+    );
+}
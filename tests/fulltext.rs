@@ -0,0 +1,109 @@
+use orm::{Connection, FromRow, Object, Timestamp, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug)]
+#[orm(soft_delete)]
+struct Article {
+    #[orm(fulltext)]
+    title: String,
+    #[orm(fulltext)]
+    body: String,
+    views: i64,
+}
+
+#[derive(FromRow)]
+struct Count {
+    n: i64,
+}
+
+fn article(title: &str, body: &str) -> Article {
+    Article {
+        title: title.into(),
+        body: body.into(),
+        views: 0,
+    }
+}
+
+fn titles(tx: &Transaction, query: &str) -> Vec<String> {
+    tx.search::<Article>(query)
+        .unwrap()
+        .into_iter()
+        .map(|hit| hit.item.borrow().title.clone())
+        .collect()
+}
+
+/// Counts the index entries matching `query`, including those of rows that
+/// no longer exist.
+fn count_indexed(tx: &Transaction, query: &str) -> i64 {
+    let sql = "SELECT COUNT(*) AS n FROM Article_fts WHERE Article_fts MATCH ?";
+    tx.query_as::<Count>(sql, &[query.into()]).unwrap()[0].n
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn search_ranks_matches_with_snippets() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(article("Printers", "How to fix a paper jam in the printer"))
+        .unwrap();
+    tx.create(article("Jam", "Strawberry jam. Jam jam jam."))
+        .unwrap();
+    tx.create(article("Coffee", "Brewing guide")).unwrap();
+
+    assert_eq!(titles(&tx, "jam"), ["Jam", "Printers"]);
+    assert_eq!(titles(&tx, "printer AND (jam OR stuck)"), ["Printers"]);
+    assert!(titles(&tx, "tea").is_empty());
+
+    let hits = tx.search::<Article>("paper").unwrap();
+    assert_eq!(hits[0].snippet, "How to fix a [paper] jam in the printer");
+    assert!(hits[0].rank < 0.0);
+}
+
+#[test]
+fn index_follows_inserts_updates_and_deletes() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.create(article("Draft", "old words")).unwrap();
+    tx.create(article("Other", "old news")).unwrap();
+    assert_eq!(count_indexed(&tx, "old"), 2);
+
+    obj.borrow_mut().body = "new words".into();
+    assert_eq!(titles(&tx, "old"), ["Other"]);
+    assert_eq!(titles(&tx, "new"), ["Draft"]);
+
+    obj.borrow_mut().views = 3;
+    assert_eq!(titles(&tx, "new"), ["Draft"]);
+
+    let c = Article::columns();
+    tx.update::<Article>()
+        .filter(c.title.eq("Other"))
+        .set(c.body, "fresh news")
+        .execute()
+        .unwrap();
+    assert!(titles(&tx, "old").is_empty());
+    assert_eq!(count_indexed(&tx, "old"), 0);
+    assert_eq!(titles(&tx, "news"), ["Other"]);
+
+    obj.delete();
+    tx.purge::<Article>(Timestamp::from_micros(i64::MAX))
+        .unwrap();
+    assert_eq!(count_indexed(&tx, "words"), 0);
+    assert_eq!(count_indexed(&tx, "news"), 1);
+}
+
+#[test]
+fn search_skips_soft_deleted_objects() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(article("Gone", "hidden text")).unwrap().id();
+    tx.create(article("Kept", "visible text")).unwrap();
+    tx.delete_by_id::<Article>(id).unwrap();
+    assert_eq!(titles(&tx, "text"), ["Kept"]);
+
+    tx.restore::<Article>(id).unwrap();
+    let mut found = titles(&tx, "text");
+    found.sort();
+    assert_eq!(found, ["Gone", "Kept"]);
+}
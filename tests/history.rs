@@ -0,0 +1,213 @@
+mod common;
+
+use common::connection_at;
+use orm::{HistoryEntry, Object, Operation, Timestamp};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+#[orm(soft_delete, audit)]
+struct Account {
+    owner: String,
+    balance: i64,
+}
+
+fn account(balance: i64) -> Account {
+    Account {
+        owner: "ann".into(),
+        balance,
+    }
+}
+
+/// The operation and the balances before and after each entry.
+fn summary(entries: &[HistoryEntry<Account>]) -> Vec<(Operation, Option<i64>, Option<i64>)> {
+    entries
+        .iter()
+        .map(|e| {
+            let balance = |obj: &Option<Account>| obj.as_ref().map(|a| a.balance);
+            (e.operation, balance(&e.before), balance(&e.after))
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn history_records_every_operation() {
+    let (mut conn, now) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    tx.set_actor("alice");
+    let id = tx.create(account(10)).unwrap().id();
+    tx.commit().unwrap();
+
+    now.set(200);
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Account>(id).unwrap().borrow_mut().balance = 20;
+    tx.commit().unwrap();
+
+    now.set(300);
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Account>(id).unwrap().delete();
+    tx.commit().unwrap();
+
+    now.set(400);
+    let tx = conn.new_transaction().unwrap();
+    tx.restore::<Account>(id).unwrap();
+    let c = Account::columns();
+    tx.update::<Account>()
+        .set(c.balance, &30i64)
+        .execute()
+        .unwrap();
+    tx.commit().unwrap();
+
+    now.set(500);
+    let tx = conn.new_transaction().unwrap();
+    tx.set_actor("bob");
+    tx.delete_by_id::<Account>(id).unwrap();
+    tx.purge::<Account>(Timestamp::from_micros(600)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let entries = tx.history::<Account>(id).unwrap();
+    assert_eq!(
+        summary(&entries),
+        [
+            (Operation::Insert, None, Some(10)),
+            (Operation::Update, Some(10), Some(20)),
+            (Operation::Delete, Some(20), None),
+            (Operation::Restore, None, Some(20)),
+            (Operation::Update, Some(20), Some(30)),
+            (Operation::Delete, Some(30), None),
+            (Operation::Purge, Some(30), None),
+        ]
+    );
+    let times = entries
+        .iter()
+        .map(|e| e.changed_at.as_micros())
+        .collect::<Vec<_>>();
+    assert_eq!(times, [100, 200, 300, 400, 400, 500, 500]);
+    let actors = entries
+        .iter()
+        .map(|e| e.actor.as_deref())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        actors,
+        [
+            Some("alice"),
+            None,
+            None,
+            None,
+            None,
+            Some("bob"),
+            Some("bob")
+        ]
+    );
+    assert_eq!(tx.verify_history().unwrap(), None);
+}
+
+#[test]
+fn unchanged_and_rolled_back_objects_are_not_recorded() {
+    let (mut conn, _) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(account(10)).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Account>(id).unwrap().borrow_mut().balance = 10;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Account>(id).unwrap().borrow_mut().balance = 50;
+    tx.rollback().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.history::<Account>(id).unwrap().len(), 1);
+}
+
+#[test]
+fn deleted_objects_can_be_modified() {
+    let (mut conn, _) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(account(10)).unwrap().id();
+    tx.delete_by_id::<Account>(id).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let all = tx.query::<Account>().with_deleted().fetch().unwrap();
+    all[0].borrow_mut().balance = 0;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let entries = tx.history::<Account>(id).unwrap();
+    assert_eq!(
+        summary(&entries)[2..],
+        [(Operation::Update, Some(10), Some(0))]
+    );
+}
+
+#[test]
+fn tampering_is_detected() {
+    let (mut conn, _) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.verify_history().unwrap(), None);
+    let id = tx.create(account(10)).unwrap().id();
+    tx.commit().unwrap();
+    for balance in [20, 30, 40] {
+        let tx = conn.new_transaction().unwrap();
+        tx.get::<Account>(id).unwrap().borrow_mut().balance = balance;
+        tx.commit().unwrap();
+    }
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.verify_history().unwrap(), None);
+    tx.execute_raw("UPDATE orm_history SET actor = 'eve' WHERE seq = 2", &[])
+        .unwrap();
+    assert_eq!(tx.verify_history().unwrap(), Some(2));
+    tx.rollback().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.execute_raw("DELETE FROM orm_history WHERE seq = 3", &[])
+        .unwrap();
+    assert_eq!(tx.verify_history().unwrap(), Some(4));
+}
+
+#[test]
+fn rewritten_history_is_detected_against_its_head() {
+    let (mut conn, _) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.history_head().unwrap(), None);
+    let id = tx.create(account(10)).unwrap().id();
+    tx.commit().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Account>(id).unwrap().borrow_mut().balance = 20;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let head = tx.history_head().unwrap().unwrap();
+    assert_eq!(head.seq, 2);
+    tx.get::<Account>(id).unwrap().borrow_mut().balance = 30;
+    tx.commit().unwrap();
+
+    // Later records do not affect an older head.
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.verify_history_with(&head).unwrap(), None);
+    assert_eq!(tx.history_head().unwrap().unwrap().seq, 3);
+
+    // Dropping the last records leaves a valid chain, which only the head
+    // reveals.
+    tx.execute_raw("DELETE FROM orm_history WHERE seq >= 2", &[])
+        .unwrap();
+    assert_eq!(tx.verify_history().unwrap(), None);
+    assert_eq!(tx.verify_history_with(&head).unwrap(), Some(2));
+    tx.rollback().unwrap();
+
+    // So does replacing them with new records.
+    let tx = conn.new_transaction().unwrap();
+    tx.execute_raw("DELETE FROM orm_history WHERE seq >= 2", &[])
+        .unwrap();
+    tx.create(account(50)).unwrap();
+    tx.commit().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.verify_history().unwrap(), None);
+    assert_eq!(tx.verify_history_with(&head).unwrap(), Some(2));
+}
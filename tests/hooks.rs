@@ -0,0 +1,145 @@
+use orm::{Connection, Error, Hooks, Object, ObjectId};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug)]
+#[orm(hooks)]
+struct Page {
+    title: String,
+    slug: String,
+    #[orm(skip)]
+    loaded: bool,
+}
+
+impl Hooks for Page {
+    fn before_insert(&mut self) -> orm::Result<()> {
+        if self.title.is_empty() {
+            return Err(Error::User("empty title".into()));
+        }
+        self.slug = self.title.to_lowercase().replace(' ', "-");
+        Ok(())
+    }
+
+    fn after_load(&mut self) -> orm::Result<()> {
+        self.loaded = true;
+        Ok(())
+    }
+
+    fn before_update(&mut self) -> orm::Result<()> {
+        if self.title.is_empty() {
+            return Err(Error::User("empty title".into()));
+        }
+        self.slug = self.title.to_lowercase().replace(' ', "-");
+        Ok(())
+    }
+
+    fn before_delete(&self) -> orm::Result<()> {
+        if self.slug == "home" {
+            return Err(Error::User("cannot delete the home page".into()));
+        }
+        Ok(())
+    }
+}
+
+fn page(title: &str) -> Page {
+    Page {
+        title: title.into(),
+        slug: String::new(),
+        loaded: false,
+    }
+}
+
+fn assert_user_error<T>(result: orm::Result<T>, message: &str) {
+    match result {
+        Err(Error::User(err)) => assert_eq!(err.to_string(), message),
+        Err(other) => panic!("expected a user error, got {:?}", other),
+        Ok(_) => panic!("expected a user error: {}", message),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn before_insert_runs_on_create() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.create(page("About Us")).unwrap();
+    assert_eq!(obj.borrow().slug, "about-us");
+    assert!(!obj.borrow().loaded);
+    let objs = tx
+        .create_many([page("Blog"), page("Contact Form")])
+        .unwrap();
+    assert_eq!(objs[1].borrow().slug, "contact-form");
+
+    assert_user_error(tx.create(page("")), "empty title");
+    assert_user_error(tx.create_many([page("Team"), page("")]), "empty title");
+    assert_eq!(tx.query::<Page>().count().unwrap(), 3);
+}
+
+#[test]
+fn after_load_runs_on_reads() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(page("About Us")).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert!(tx.get::<Page>(id).unwrap().borrow().loaded);
+    let detached = tx.query::<Page>().iter_detached().unwrap().next();
+    assert!(detached.unwrap().unwrap().loaded);
+    let raw = tx.query_raw_detached::<Page>("SELECT * FROM Page", &[]);
+    assert!(raw.unwrap()[0].loaded);
+}
+
+#[test]
+fn before_update_runs_on_flush() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(page("About Us")).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.get::<Page>(id).unwrap();
+    obj.borrow_mut().title = "About Them".into();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<Page>(id).unwrap().borrow().slug, "about-them");
+}
+
+#[test]
+fn hook_errors_abort_commit() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let home = tx.create(page("Home")).unwrap().id();
+    let about = tx.create(page("About")).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Page>(about).unwrap().borrow_mut().title = "".into();
+    assert_user_error(tx.commit(), "empty title");
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Page>(about).unwrap().borrow_mut().title = "Company".into();
+    tx.get::<Page>(home).unwrap().delete();
+    assert_user_error(tx.commit(), "cannot delete the home page");
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<Page>(about).unwrap().borrow().slug, "about");
+    assert_eq!(tx.query::<Page>().count().unwrap(), 2);
+}
+
+#[test]
+fn deletes_without_loading_skip_before_delete() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(page("Home")).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.delete_by_id::<Page>(ObjectId::from(1)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.query::<Page>().count().unwrap(), 0);
+}
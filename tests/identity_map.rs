@@ -0,0 +1,225 @@
+use orm::{Connection, Object, ObjectId, ObjectState};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+struct Author {
+    name: String,
+}
+
+#[derive(Object, Debug, PartialEq)]
+struct Book {
+    title: String,
+    pages: i64,
+}
+
+#[derive(Object, Debug, PartialEq)]
+struct Tag {
+    #[orm(primary_key)]
+    id: i64,
+    label: String,
+}
+
+#[derive(Object, Debug, PartialEq)]
+struct Label {
+    #[orm(primary_key)]
+    id: i64,
+    text: String,
+}
+
+fn author(name: &str) -> Author {
+    Author { name: name.into() }
+}
+
+fn book(title: &str, pages: i64) -> Book {
+    Book {
+        title: title.into(),
+        pages,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn create_with_overlapping_ids() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+
+    let a = tx.create(author("Tolstoy")).unwrap();
+    let b = tx.create(book("War and Peace", 1225)).unwrap();
+    assert_eq!(a.id(), b.id());
+
+    assert_eq!(*a.borrow(), author("Tolstoy"));
+    assert_eq!(*b.borrow(), book("War and Peace", 1225));
+    assert_eq!(
+        *tx.get::<Author>(a.id()).unwrap().borrow(),
+        author("Tolstoy")
+    );
+    assert_eq!(
+        *tx.get::<Book>(b.id()).unwrap().borrow(),
+        book("War and Peace", 1225)
+    );
+}
+
+#[test]
+fn get_with_overlapping_ids() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(author("Tolstoy")).unwrap().id();
+    tx.create(book("War and Peace", 1225)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let a = tx.get::<Author>(id).unwrap();
+    let b = tx.get::<Book>(id).unwrap();
+    assert_eq!(*a.borrow(), author("Tolstoy"));
+    assert_eq!(*b.borrow(), book("War and Peace", 1225));
+}
+
+#[test]
+fn modify_with_overlapping_ids() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(author("Tolstoy")).unwrap().id();
+    tx.create(book("War and Peace", 1225)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let b = tx.get::<Book>(id).unwrap();
+    b.borrow_mut().pages = 1300;
+    let a = tx.get::<Author>(id).unwrap();
+    assert_eq!(a.state(), ObjectState::Clean);
+    assert_eq!(b.state(), ObjectState::Modified);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<Author>(id).unwrap().state(), ObjectState::Clean);
+    assert_eq!(*tx.get::<Author>(id).unwrap().borrow(), author("Tolstoy"));
+    assert_eq!(tx.get::<Book>(id).unwrap().borrow().pages, 1300);
+}
+
+#[test]
+fn delete_with_overlapping_ids() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(author("Tolstoy")).unwrap().id();
+    tx.create(book("War and Peace", 1225)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Author>(id).unwrap().delete();
+    assert!(tx.get::<Author>(id).is_err());
+    assert_eq!(
+        *tx.get::<Book>(id).unwrap().borrow(),
+        book("War and Peace", 1225)
+    );
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(tx.get::<Author>(id), Err(orm::Error::NotFound(_))));
+    assert_eq!(tx.get::<Book>(id).unwrap().borrow().pages, 1225);
+}
+
+#[test]
+fn lookup_with_overlapping_ids() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let a = tx.create(author("Tolstoy")).unwrap();
+    let b = tx.create(book("Tolstoy", 1225)).unwrap();
+
+    let found = tx.get_by(Book::columns().title, "Tolstoy").unwrap();
+    assert_eq!(found.id(), b.id());
+    assert_eq!(found.borrow().pages, 1225);
+    let found = tx.get_by(Author::columns().name, "Tolstoy").unwrap();
+    assert_eq!(found.id(), a.id());
+    assert_eq!(*found.borrow(), author("Tolstoy"));
+}
+
+#[test]
+fn natural_keys_with_overlapping_values() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Tag {
+        id: 7,
+        label: "fiction".into(),
+    })
+    .unwrap();
+    tx.create(Label {
+        id: 7,
+        text: "shelf".into(),
+    })
+    .unwrap();
+    tx.create(author("Tolstoy")).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<Tag>(7).unwrap().borrow().label, "fiction");
+    assert_eq!(tx.get::<Label>(7).unwrap().borrow().text, "shelf");
+    assert_eq!(
+        *tx.get::<Author>(ObjectId::from(1)).unwrap().borrow(),
+        author("Tolstoy")
+    );
+}
+
+#[test]
+fn modifying_natural_keys_is_an_error() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Tag {
+        id: 7,
+        label: "fiction".into(),
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let tag = tx.get::<Tag>(7).unwrap();
+    tag.borrow_mut().id = 8;
+    tag.borrow_mut().label = "poetry".into();
+    match tx.flush() {
+        Err(orm::Error::KeyChanged(err)) => {
+            assert_eq!(err.type_name, "Tag");
+            assert_eq!(err.key, "id = 7");
+        }
+        other => panic!("expected KeyChanged, got {:?}", other),
+    }
+    assert!(tx.commit().is_err());
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<Tag>(7).unwrap().borrow().label, "fiction");
+    assert!(matches!(tx.get::<Tag>(8), Err(orm::Error::NotFound(_))));
+}
+
+#[test]
+fn flush_with_borrowed_objects() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(author("Tolstoy")).unwrap().id();
+    tx.create(book("War and Peace", 1225)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let a = tx.get::<Author>(id).unwrap();
+    let b = tx.get::<Book>(id).unwrap();
+    let clean = a.borrow();
+    assert_eq!(tx.query::<Author>().count().unwrap(), 1);
+    tx.flush().unwrap();
+
+    b.borrow_mut().pages = 1300;
+    {
+        let _modified = b.borrow();
+        assert!(matches!(tx.flush(), Err(orm::Error::ObjectBorrowed)));
+    }
+    tx.flush().unwrap();
+    assert_eq!(b.state(), ObjectState::Clean);
+
+    let update = tx.update::<Author>().set(Author::columns().name, "Chekhov");
+    assert!(matches!(update.execute(), Err(orm::Error::ObjectBorrowed)));
+    drop(clean);
+    assert_eq!(a.borrow().name, "Tolstoy");
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<Author>(id).unwrap().borrow().name, "Tolstoy");
+    assert_eq!(tx.get::<Book>(id).unwrap().borrow().pages, 1300);
+}
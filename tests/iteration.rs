@@ -0,0 +1,103 @@
+use orm::{Connection, Object, ObjectId, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+struct Item {
+    n: i64,
+    bucket: i64,
+}
+
+/// More rows than are read in one chunk.
+const COUNT: i64 = 2500;
+
+fn create_items(tx: &Transaction) {
+    tx.create_many((0..COUNT).map(|n| Item { n, bucket: n % 3 }))
+        .unwrap();
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn iter_reads_all_chunks() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_items(&tx);
+    let items = tx
+        .query::<Item>()
+        .iter()
+        .unwrap()
+        .map(|item| item.unwrap().borrow().n)
+        .collect::<Vec<_>>();
+    assert_eq!(items, (0..COUNT).collect::<Vec<_>>());
+
+    let c = Item::columns();
+    let count = tx
+        .query::<Item>()
+        .filter(c.bucket.eq(&1i64))
+        .iter_detached()
+        .unwrap()
+        .count();
+    assert_eq!(count as i64, COUNT / 3);
+}
+
+#[test]
+fn iter_with_descending_order_and_ties() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_items(&tx);
+    let items = tx
+        .query::<Item>()
+        .order_by_desc(Item::columns().bucket)
+        .iter_detached()
+        .unwrap()
+        .map(|item| item.unwrap().n)
+        .collect::<Vec<_>>();
+    let mut expected = (0..COUNT).collect::<Vec<_>>();
+    expected.sort_by_key(|n| (-(n % 3), *n));
+    assert_eq!(items, expected);
+}
+
+#[test]
+fn iter_with_limit_and_offset() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_items(&tx);
+    let c = Item::columns();
+    let items = |offset, limit| {
+        tx.query::<Item>()
+            .order_by_desc(c.n)
+            .offset(offset)
+            .limit(limit)
+            .iter()
+            .unwrap()
+            .map(|item| item.unwrap().borrow().n)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(items(1000, 1100), (400..1500).rev().collect::<Vec<_>>());
+    assert_eq!(items(2490, 100), (0..10).rev().collect::<Vec<_>>());
+    assert!(items(0, 0).is_empty());
+    assert!(items(COUNT as u64, 10).is_empty());
+}
+
+#[test]
+fn iter_detached_does_not_cache_objects() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_items(&tx);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    for item in tx.query::<Item>().iter_detached().unwrap() {
+        item.unwrap().n += 1;
+    }
+    // Cached objects are not refreshed by raw statements, so the new value
+    // is only seen if the object was not loaded before.
+    tx.execute_raw("UPDATE Item SET n = -1", &[]).unwrap();
+    let item = tx.get::<Item>(ObjectId::from(1)).unwrap();
+    assert_eq!(item.borrow().n, -1);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.query::<Item>().sum(Item::columns().n).unwrap(), -COUNT);
+}
@@ -0,0 +1,218 @@
+use orm::{Connection, Error, Object, ObjectId, Timestamp, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+#[orm(soft_delete)]
+struct Department {
+    name: String,
+}
+
+#[derive(Object, Debug, PartialEq)]
+#[orm(soft_delete)]
+struct Employee {
+    name: String,
+    #[orm(references = Department)]
+    department: ObjectId,
+}
+
+#[derive(Object, Debug)]
+struct Team {
+    name: String,
+}
+
+#[derive(Object, Debug)]
+struct Player {
+    name: String,
+    #[orm(references = Team)]
+    team: ObjectId,
+}
+
+#[derive(Object, Debug)]
+struct Task {
+    #[orm(references = Task)]
+    parent: ObjectId,
+    assignee: ObjectId,
+}
+
+/// Creates the departments "sales" and "ops" and three employees, returning
+/// the department ids.
+fn create_staff(tx: &Transaction) -> (ObjectId, ObjectId) {
+    let sales = tx.create(department("sales")).unwrap().id();
+    let ops = tx.create(department("ops")).unwrap().id();
+    for (name, department) in [("ann", sales), ("bob", ops), ("cid", sales)] {
+        tx.create(Employee {
+            name: name.into(),
+            department,
+        })
+        .unwrap();
+    }
+    (sales, ops)
+}
+
+fn department(name: &str) -> Department {
+    Department { name: name.into() }
+}
+
+fn names<'t>(
+    pairs: impl IntoIterator<Item = (orm::Tx<'t, Employee>, Option<String>)>,
+) -> Vec<(String, Option<String>)> {
+    pairs
+        .into_iter()
+        .map(|(e, d)| (e.borrow().name.clone(), d))
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn inner_join_pairs_objects() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_staff(&tx);
+    let e = Employee::columns();
+    let pairs = tx
+        .query::<Employee>()
+        .join::<Department>(e.department)
+        .order_by(e.name)
+        .fetch()
+        .unwrap();
+    let pairs = pairs
+        .into_iter()
+        .map(|(e, d)| (e, Some(d.borrow().name.clone())));
+    assert_eq!(
+        names(pairs),
+        [
+            ("ann".into(), Some("sales".into())),
+            ("bob".into(), Some("ops".into())),
+            ("cid".into(), Some("sales".into())),
+        ]
+    );
+
+    let pairs = tx
+        .query::<Employee>()
+        .join::<Department>(e.department)
+        .filter_joined(Department::columns().name.eq("sales"))
+        .order_by_desc(e.name)
+        .fetch()
+        .unwrap();
+    let found = pairs
+        .iter()
+        .map(|(e, _)| e.borrow().name.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(found, ["cid", "ann"]);
+    assert_eq!(pairs[0].1.id(), pairs[1].1.id());
+}
+
+#[test]
+fn joins_skip_soft_deleted_objects() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let (sales, _) = create_staff(&tx);
+    tx.delete_by_id::<Department>(sales).unwrap();
+    tx.get_by(Employee::columns().name, "bob").unwrap().delete();
+    let e = Employee::columns();
+
+    let inner = tx
+        .query::<Employee>()
+        .join::<Department>(e.department)
+        .fetch()
+        .unwrap();
+    assert!(inner.is_empty());
+
+    let left = tx
+        .query::<Employee>()
+        .left_join::<Department>(e.department)
+        .order_by(e.name)
+        .fetch()
+        .unwrap();
+    let left = left
+        .into_iter()
+        .map(|(e, d)| (e, d.map(|d| d.borrow().name.clone())));
+    assert_eq!(names(left), [("ann".into(), None), ("cid".into(), None)]);
+
+    let all = tx
+        .query::<Employee>()
+        .left_join::<Department>(e.department)
+        .with_deleted()
+        .order_by(e.name)
+        .fetch()
+        .unwrap();
+    let all = all
+        .into_iter()
+        .map(|(e, d)| (e, d.map(|d| d.borrow().name.clone())));
+    assert_eq!(
+        names(all),
+        [
+            ("ann".into(), Some("sales".into())),
+            ("bob".into(), Some("ops".into())),
+            ("cid".into(), Some("sales".into())),
+        ]
+    );
+}
+
+#[test]
+fn references_are_enforced() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let result = tx.create(Employee {
+        name: "ann".into(),
+        department: ObjectId::from(7),
+    });
+    assert!(matches!(result, Err(Error::ConstraintViolation(_))));
+
+    let (sales, _) = create_staff(&tx);
+    tx.delete_by_id::<Department>(sales).unwrap();
+    tx.flush().unwrap();
+    let result = tx.purge::<Department>(Timestamp::from_micros(i64::MAX));
+    assert!(matches!(result, Err(Error::ConstraintViolation(_))));
+}
+
+#[test]
+fn deleting_referenced_objects_is_a_violation() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let team = tx.create(Team { name: "red".into() }).unwrap();
+    let id = team.id();
+    tx.create(Player {
+        name: "ann".into(),
+        team: id,
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Team>(id).unwrap().delete();
+    match tx.flush() {
+        Err(Error::ConstraintViolation(err)) => assert_eq!(err.table_name, "Team"),
+        other => panic!("expected ConstraintViolation, got {:?}", other),
+    }
+    tx.rollback().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.delete_by_id::<Team>(id).unwrap();
+    assert!(matches!(tx.commit(), Err(Error::ConstraintViolation(_))));
+
+    let tx = conn.new_transaction().unwrap();
+    tx.delete::<Player>().execute().unwrap();
+    tx.delete_by_id::<Team>(id).unwrap();
+    tx.commit().unwrap();
+}
+
+#[test]
+fn joins_need_a_reference_to_another_type() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let t = Task::columns();
+    match tx.query::<Task>().join::<Employee>(t.assignee).fetch() {
+        Err(Error::InvalidJoin(err)) => {
+            assert_eq!(err.type_name, "Task");
+            assert_eq!(err.column_name, "assignee");
+            assert_eq!(err.joined_type_name, "Employee");
+        }
+        Err(other) => panic!("expected InvalidJoin, got {:?}", other),
+        Ok(_) => panic!("expected InvalidJoin"),
+    }
+    let result = tx.query::<Task>().left_join::<Task>(t.parent).fetch();
+    assert!(matches!(result, Err(Error::InvalidJoin(_))));
+}
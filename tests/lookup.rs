@@ -0,0 +1,62 @@
+use orm::{Connection, Error, Object};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+struct Author {
+    name: String,
+    born: i64,
+}
+
+fn author(name: &str, born: i64) -> Author {
+    Author {
+        name: name.into(),
+        born,
+    }
+}
+
+fn assert_not_found<T>(result: orm::Result<T>, key: &str) {
+    match result {
+        Err(Error::NotFound(err)) => {
+            assert_eq!(err.type_name, "Author");
+            assert_eq!(err.key, key);
+        }
+        Err(other) => panic!("expected NotFound, got {:?}", other),
+        Ok(_) => panic!("expected NotFound for {}", key),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn lookup_sees_pending_changes() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(author("Tolstoy", 1828)).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let a = tx.get::<Author>(id).unwrap();
+    a.borrow_mut().name = "Chekhov".into();
+    let name = Author::columns().name;
+    assert_eq!(tx.get_by(name, "Chekhov").unwrap().id(), a.id());
+    assert!(tx.find_one(name, "Tolstoy").unwrap().is_none());
+}
+
+#[test]
+fn not_found_by_error() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(author("Tolstoy", 1828)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let columns = Author::columns();
+    assert_not_found(tx.get_by(columns.name, "Chekhov"), "name = \"Chekhov\"");
+    assert_not_found(tx.get_by(columns.born, &1860i64), "born = 1860");
+    assert!(tx.find_one(columns.born, &1860i64).unwrap().is_none());
+    assert_eq!(
+        tx.get_by(columns.born, &1828i64).unwrap().borrow().name,
+        "Tolstoy"
+    );
+}
@@ -0,0 +1,134 @@
+use orm::{Connection, Error, Object, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+struct Post {
+    title: String,
+    votes: i64,
+}
+
+fn create_posts(tx: &Transaction, votes: impl IntoIterator<Item = i64>) {
+    for v in votes {
+        tx.create(Post {
+            title: format!("post {}", v),
+            votes: v,
+        })
+        .unwrap();
+    }
+}
+
+fn votes(items: &[orm::Tx<'_, Post>]) -> Vec<i64> {
+    items.iter().map(|p| p.borrow().votes).collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn pages_report_totals() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_posts(&tx, 0..23);
+    let c = Post::columns();
+    let query = || tx.query::<Post>().order_by_desc(c.votes).paginate(10);
+
+    let page = query().page(1).unwrap();
+    assert_eq!(page.total, 23);
+    assert_eq!(page.page_count(), 3);
+    assert_eq!(votes(&page.items), (3..13).rev().collect::<Vec<_>>());
+    assert_eq!(votes(&query().page(2).unwrap().items), [2, 1, 0]);
+
+    let page = query().page(7).unwrap();
+    assert!(page.items.is_empty());
+    assert_eq!(page.total, 23);
+
+    let page = tx
+        .query::<Post>()
+        .filter(c.votes.lt(&5i64))
+        .limit(1)
+        .paginate(2)
+        .page(0)
+        .unwrap();
+    assert_eq!((page.total, page.page_count()), (5, 3));
+    assert_eq!(votes(&page.items), [0, 1]);
+}
+
+#[test]
+fn cursors_walk_all_pages() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_posts(&tx, [3, 1, 3, 2, 1, 3, 2]);
+    let c = Post::columns();
+    let mut cursor = None;
+    let mut pages = Vec::new();
+    loop {
+        let page = tx
+            .query::<Post>()
+            .order_by_desc(c.votes)
+            .paginate(3)
+            .after(cursor.as_deref())
+            .unwrap();
+        pages.push(votes(&page.items));
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    assert_eq!(pages, [vec![3, 3, 3], vec![2, 2, 1], vec![1]]);
+}
+
+#[test]
+fn cursors_are_stable_across_inserts_and_deletes() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_posts(&tx, 0..10);
+    let c = Post::columns();
+    let query = || tx.query::<Post>().order_by(c.votes).paginate(4);
+    let first = query().after(None).unwrap();
+    assert_eq!(votes(&first.items), [0, 1, 2, 3]);
+    let cursor = first.next_cursor.unwrap();
+
+    create_posts(&tx, [-1, 2]);
+    first.items[0].clone().delete();
+    first.items[3].clone().delete();
+    let second = query().after(Some(&cursor)).unwrap();
+    assert_eq!(votes(&second.items), [4, 5, 6, 7]);
+    let third = query().after(second.next_cursor.as_deref()).unwrap();
+    assert_eq!(votes(&third.items), [8, 9]);
+    assert_eq!(third.next_cursor, None);
+}
+
+#[test]
+fn corrupt_cursors_are_rejected() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_posts(&tx, 0..5);
+    let c = Post::columns();
+    let cursor = tx
+        .query::<Post>()
+        .paginate(2)
+        .after(None)
+        .unwrap()
+        .next_cursor
+        .unwrap();
+    let after = |cursor: &str| {
+        tx.query::<Post>()
+            .order_by(c.votes)
+            .paginate(2)
+            .after(Some(cursor))
+    };
+    assert!(matches!(after("not a cursor"), Err(Error::InvalidCursor)));
+    assert!(matches!(after(&cursor[1..]), Err(Error::InvalidCursor)));
+    // A cursor from a query with a different ordering.
+    assert!(matches!(after(&cursor), Err(Error::InvalidCursor)));
+}
+
+#[test]
+fn empty_pages_are_rejected() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_posts(&tx, [1, 2, 3]);
+    let query = || tx.query::<Post>().paginate(0);
+    assert!(matches!(query().page(0), Err(Error::InvalidPageSize)));
+    assert!(matches!(query().after(None), Err(Error::InvalidPageSize)));
+}
@@ -0,0 +1,153 @@
+use orm::{Connection, Error, Object, ObjectState};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+struct Enrollment {
+    #[orm(primary_key)]
+    student: String,
+    #[orm(primary_key)]
+    course: i64,
+    grade: i64,
+}
+
+fn enrollment(student: &str, course: i64, grade: i64) -> Enrollment {
+    Enrollment {
+        student: student.into(),
+        course,
+        grade,
+    }
+}
+
+fn key(student: &str, course: i64) -> (String, i64) {
+    (student.into(), course)
+}
+
+fn assert_not_found<T>(result: orm::Result<T>, key: &str) {
+    match result {
+        Err(Error::NotFound(err)) => {
+            assert_eq!(err.type_name, "Enrollment");
+            assert_eq!(err.key, key);
+        }
+        Err(other) => panic!("expected NotFound, got {:?}", other),
+        Ok(_) => panic!("expected NotFound for {}", key),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn create_and_get() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let e = tx.create(enrollment("ann", 1, 90)).unwrap();
+    assert_eq!(e.id(), key("ann", 1));
+    tx.create(enrollment("ann", 2, 80)).unwrap();
+    tx.create(enrollment("bob", 1, 70)).unwrap();
+    assert_eq!(
+        tx.get::<Enrollment>(key("ann", 1)).unwrap().borrow().grade,
+        90
+    );
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        *tx.get::<Enrollment>(key("ann", 2)).unwrap().borrow(),
+        enrollment("ann", 2, 80)
+    );
+    assert_eq!(
+        tx.get::<Enrollment>(key("bob", 1)).unwrap().borrow().grade,
+        70
+    );
+    assert_not_found(
+        tx.get::<Enrollment>(key("bob", 2)),
+        "student = \"bob\", course = 2",
+    );
+}
+
+#[test]
+fn update() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(enrollment("ann", 1, 90)).unwrap();
+    tx.create(enrollment("ann", 2, 80)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let e = tx.get::<Enrollment>(key("ann", 1)).unwrap();
+    e.borrow_mut().grade = 95;
+    assert_eq!(e.state(), ObjectState::Modified);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        tx.get::<Enrollment>(key("ann", 1)).unwrap().borrow().grade,
+        95
+    );
+    assert_eq!(
+        tx.get::<Enrollment>(key("ann", 2)).unwrap().borrow().grade,
+        80
+    );
+}
+
+#[test]
+fn delete() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(enrollment("ann", 1, 90)).unwrap();
+    tx.create(enrollment("ann", 2, 80)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Enrollment>(key("ann", 1)).unwrap().delete();
+    assert_not_found(
+        tx.get::<Enrollment>(key("ann", 1)),
+        "student = \"ann\", course = 1",
+    );
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_not_found(
+        tx.get::<Enrollment>(key("ann", 1)),
+        "student = \"ann\", course = 1",
+    );
+    assert_eq!(tx.query::<Enrollment>().count().unwrap(), 1);
+    tx.create(enrollment("ann", 1, 60)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        tx.get::<Enrollment>(key("ann", 1)).unwrap().borrow().grade,
+        60
+    );
+}
+
+#[test]
+fn modifying_key_fields_is_an_error() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(enrollment("ann", 1, 90)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let e = tx.get::<Enrollment>(key("ann", 1)).unwrap();
+    e.borrow_mut().course = 2;
+    match tx.flush() {
+        Err(Error::KeyChanged(err)) => {
+            assert_eq!(err.type_name, "Enrollment");
+            assert_eq!(err.key, "student = \"ann\", course = 1");
+        }
+        other => panic!("expected KeyChanged, got {:?}", other),
+    }
+    assert!(tx.commit().is_err());
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(
+        tx.get::<Enrollment>(key("ann", 1)).unwrap().borrow().grade,
+        90
+    );
+    assert_not_found(
+        tx.get::<Enrollment>(key("ann", 2)),
+        "student = \"ann\", course = 2",
+    );
+}
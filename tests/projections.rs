@@ -0,0 +1,163 @@
+use orm::{data::DataType, Connection, Error, FromRow, Object, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug)]
+struct City {
+    name: String,
+    country: String,
+    population: i64,
+    area: f64,
+    capital: bool,
+}
+
+#[derive(FromRow, Debug, PartialEq)]
+struct Summary {
+    name: String,
+    population: i64,
+}
+
+#[derive(FromRow, Debug, PartialEq)]
+struct Census {
+    #[column_name("country")]
+    nation: String,
+    cities: i64,
+    total: i64,
+}
+
+#[derive(FromRow, Debug)]
+#[allow(dead_code)]
+struct Tiny {
+    population: i16,
+}
+
+fn create_cities(tx: &Transaction) {
+    for (name, country, population, area, capital) in [
+        ("Lyon", "FR", 520_000, 47.9, false),
+        ("Paris", "FR", 2_100_000, 105.4, true),
+        ("Rome", "IT", 2_800_000, 1285.0, true),
+    ] {
+        tx.create(City {
+            name: name.into(),
+            country: country.into(),
+            population,
+            area,
+            capital,
+        })
+        .unwrap();
+    }
+}
+
+fn summary(name: &str, population: i64) -> Summary {
+    Summary {
+        name: name.into(),
+        population,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn select_columns_and_tuples() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_cities(&tx);
+    let c = City::columns();
+    let names = tx
+        .query::<City>()
+        .order_by_desc(c.population)
+        .select(c.name)
+        .unwrap();
+    assert_eq!(names, ["Rome", "Paris", "Lyon"]);
+
+    let rows = tx
+        .query::<City>()
+        .filter(c.capital.eq(&true))
+        .order_by(c.name)
+        .select((c.name, c.area, c.capital))
+        .unwrap();
+    assert_eq!(
+        rows,
+        [
+            ("Paris".to_string(), 105.4, true),
+            ("Rome".to_string(), 1285.0, true)
+        ]
+    );
+}
+
+#[test]
+fn select_as_matches_fields_by_name() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_cities(&tx);
+    let c = City::columns();
+    let rows = tx
+        .query::<City>()
+        .filter(c.country.eq("FR"))
+        .order_by(c.name)
+        .select_as::<Summary>()
+        .unwrap();
+    assert_eq!(
+        rows,
+        [summary("Lyon", 520_000), summary("Paris", 2_100_000)]
+    );
+}
+
+#[test]
+fn query_as_decodes_computed_columns() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_cities(&tx);
+    let rows = tx
+        .query_as::<Census>(
+            "SELECT country, COUNT(*) AS cities, SUM(population) AS total \
+             FROM City GROUP BY country ORDER BY country",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(
+        rows,
+        [
+            Census {
+                nation: "FR".into(),
+                cities: 2,
+                total: 2_620_000
+            },
+            Census {
+                nation: "IT".into(),
+                cities: 1,
+                total: 2_800_000
+            },
+        ]
+    );
+    let rows = tx
+        .query_as::<Summary>("SELECT population, 'x' || name AS name FROM City", &[])
+        .unwrap();
+    assert_eq!(rows[0], summary("xLyon", 520_000));
+}
+
+#[test]
+fn decoding_errors_name_the_row_field() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    create_cities(&tx);
+    match tx.query::<City>().select_as::<Tiny>() {
+        Err(Error::OutOfRange(err)) => {
+            assert_eq!(err.type_name, "Tiny");
+            assert_eq!(err.column_name, "population");
+        }
+        other => panic!("expected OutOfRange, got {:?}", other),
+    }
+    match tx.query_as::<Summary>("SELECT name, NULL AS population FROM City", &[]) {
+        Err(Error::UnexpectedType(err)) => {
+            assert_eq!(err.type_name, "Summary");
+            assert_eq!(err.attr_name, "population");
+            assert_eq!(err.expected_type, DataType::Int64);
+        }
+        other => panic!("expected UnexpectedType, got {:?}", other),
+    }
+    match tx.query_as::<Summary>("SELECT name FROM City", &[]) {
+        Err(Error::MissingColumn(err)) => assert_eq!(err.column_name, "population"),
+        other => panic!("expected MissingColumn, got {:?}", other),
+    }
+}
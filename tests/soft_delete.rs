@@ -0,0 +1,159 @@
+mod common;
+
+use common::connection_at;
+use orm::{Connection, Error, FromRow, Object, ObjectState, Timestamp};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug, PartialEq)]
+#[orm(soft_delete)]
+struct Document {
+    name: String,
+    tags: Vec<String>,
+}
+
+#[derive(Object, Debug)]
+struct Memo {
+    text: String,
+}
+
+/// A soft-deletable type over the table of `Memo`, which has no `deleted_at`
+/// column.
+#[derive(Object, Debug)]
+#[table_name("Memo")]
+#[orm(soft_delete)]
+struct ArchivedMemo {
+    text: String,
+}
+
+#[derive(FromRow)]
+struct Count {
+    n: i64,
+}
+
+fn document(name: &str) -> Document {
+    Document {
+        name: name.into(),
+        tags: vec![name.to_uppercase()],
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn deleted_objects_are_hidden_and_kept() {
+    let (mut conn, _) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(document("a")).unwrap().id();
+    tx.create(document("b")).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Document>(id).unwrap().delete();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(tx.get::<Document>(id), Err(Error::NotFound(_))));
+    assert_eq!(tx.query::<Document>().count().unwrap(), 1);
+    let all = tx.query::<Document>().with_deleted().fetch().unwrap();
+    assert_eq!(all.len(), 2);
+    assert_eq!(*all[0].borrow(), document("a"));
+}
+
+#[test]
+fn deleted_objects_loaded_with_deleted_are_not_found() {
+    let (mut conn, _) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let deleted = tx.create(document("a")).unwrap().id();
+    let live = tx.create(document("b")).unwrap().id();
+    tx.delete_by_id::<Document>(deleted).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let all = tx.query::<Document>().with_deleted().fetch().unwrap();
+    assert_eq!(all.len(), 2);
+    assert!(matches!(
+        tx.get::<Document>(deleted),
+        Err(Error::NotFound(_))
+    ));
+    assert_eq!(*tx.get::<Document>(live).unwrap().borrow(), document("b"));
+
+    let obj = tx.restore::<Document>(deleted).unwrap();
+    assert_eq!(obj.id(), all[0].id());
+    assert_eq!(
+        *tx.get::<Document>(deleted).unwrap().borrow(),
+        document("a")
+    );
+}
+
+#[test]
+fn restore_brings_objects_back() {
+    let (mut conn, _) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(document("a")).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.restore::<Document>(id),
+        Err(Error::NotFound(_))
+    ));
+    tx.delete_by_id::<Document>(id).unwrap();
+    let obj = tx.restore::<Document>(id).unwrap();
+    assert_eq!(obj.state(), ObjectState::Clean);
+    assert_eq!(*obj.borrow(), document("a"));
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(*tx.get::<Document>(id).unwrap().borrow(), document("a"));
+}
+
+#[test]
+fn purge_deletes_objects_deleted_before_the_cutoff() {
+    let (mut conn, now) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let old = tx.create(document("old")).unwrap().id();
+    let recent = tx.create(document("recent")).unwrap().id();
+    tx.create(document("live")).unwrap();
+    tx.delete_by_id::<Document>(old).unwrap();
+    tx.flush().unwrap();
+    now.set(200);
+    tx.delete_by_id::<Document>(recent).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let purged = tx.purge::<Document>(Timestamp::from_micros(150)).unwrap();
+    assert_eq!(purged, 1);
+    assert!(matches!(
+        tx.restore::<Document>(old),
+        Err(Error::NotFound(_))
+    ));
+    tx.restore::<Document>(recent).unwrap();
+    assert_eq!(tx.query::<Document>().with_deleted().count().unwrap(), 2);
+    let sql = "SELECT COUNT(*) AS n FROM Document_tags";
+    assert_eq!(tx.query_as::<Count>(sql, &[]).unwrap()[0].n, 2);
+    let tags = tx
+        .query::<Document>()
+        .fetch()
+        .unwrap()
+        .iter()
+        .map(|d| d.borrow().tags.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(tags, [vec!["RECENT"], vec!["LIVE"]]);
+}
+
+#[test]
+fn missing_deleted_at_column_is_reported() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Memo { text: "a".into() }).unwrap();
+    match tx.query::<ArchivedMemo>().fetch() {
+        Err(Error::MissingColumn(err)) => {
+            assert_eq!(err.type_name, "ArchivedMemo");
+            assert_eq!(err.table_name, "Memo");
+            assert_eq!(err.column_name, "deleted_at");
+        }
+        Err(other) => panic!("expected MissingColumn, got {:?}", other),
+        Ok(_) => panic!("expected MissingColumn"),
+    }
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::connection_at;
+use orm::{Object, Timestamp};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug)]
+struct Ticket {
+    subject: String,
+    #[orm(created_at)]
+    opened: Timestamp,
+    #[orm(updated_at)]
+    touched: Timestamp,
+}
+
+fn ticket(subject: &str) -> Ticket {
+    Ticket {
+        subject: subject.into(),
+        opened: Timestamp::from_micros(0),
+        touched: Timestamp::from_micros(0),
+    }
+}
+
+fn micros(ticket: &orm::Tx<'_, Ticket>) -> (i64, i64) {
+    let ticket = ticket.borrow();
+    (ticket.opened.as_micros(), ticket.touched.as_micros())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn timestamps_are_set_on_insert() {
+    let (mut conn, now) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let first = tx.create(ticket("a")).unwrap();
+    assert_eq!(micros(&first), (100, 100));
+    let id = first.id();
+    now.set(200);
+    let many = tx.create_many([ticket("b"), ticket("c")]).unwrap();
+    assert_eq!(micros(&many[1]), (200, 200));
+    tx.commit().unwrap();
+
+    now.set(300);
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(micros(&tx.get::<Ticket>(id).unwrap()), (100, 100));
+}
+
+#[test]
+fn updated_at_is_set_on_flush_of_modified_objects() {
+    let (mut conn, now) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(ticket("a")).unwrap().id();
+    let other = tx.create(ticket("b")).unwrap().id();
+    tx.commit().unwrap();
+
+    now.set(200);
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx.get::<Ticket>(id).unwrap();
+    tx.get::<Ticket>(other).unwrap();
+    obj.borrow_mut().subject = "changed".into();
+    assert_eq!(micros(&obj), (100, 100));
+    now.set(300);
+    tx.commit().unwrap();
+
+    now.set(400);
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(micros(&tx.get::<Ticket>(id).unwrap()), (100, 300));
+    assert_eq!(micros(&tx.get::<Ticket>(other).unwrap()), (100, 100));
+}
+
+#[test]
+fn explicit_values_are_overwritten() {
+    let (mut conn, now) = connection_at(100);
+    let tx = conn.new_transaction().unwrap();
+    let obj = tx
+        .create(Ticket {
+            subject: "a".into(),
+            opened: Timestamp::from_micros(5),
+            touched: Timestamp::from_micros(5),
+        })
+        .unwrap();
+    assert_eq!(micros(&obj), (100, 100));
+
+    now.set(200);
+    obj.borrow_mut().touched = Timestamp::from_micros(7);
+    tx.flush().unwrap();
+    assert_eq!(micros(&obj), (100, 200));
+}